@@ -0,0 +1,336 @@
+//! `miniqctl`: operate a miniq broker over its HTTP API.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+use miniq_client::{model, Client, Error, QueueQuery};
+
+const USAGE: &str = "Usage: miniqctl [OPTIONS] <COMMAND>
+
+Commands:
+    list [--prefix <P>] [--sort name|depth]
+                             queues with their depth
+    create <QUEUE>           create a queue
+    delete <QUEUE>           delete a queue and its messages
+    show <QUEUE>             depth, publishers and subscribers
+    publish <QUEUE> [FILE...] [--priority <N>] [--lifetime <SECONDS>] [--lines]
+                             one message per file, or stdin when no file is given;
+                             --lines sends every input line as its own message
+    tail <QUEUE> [--from-start]
+                             print messages as they arrive
+    dump [FILE]              write broker state as JSON, to stdout if no file
+    restore [FILE]           load broker state written by dump, from stdin if no file
+    stats                    broker version, uptime, memory and queue totals
+
+Options:
+    -s, --server <URL>       broker address, default http://localhost:8080   [env: MINIQ_URL]
+    -o, --output <FORMAT>    table or json, default table
+    -h, --help               print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Output
+{
+    Table,
+    Json,
+}
+
+/// Options before and after the command, split from its positional arguments.
+struct Args
+{
+    server:     String,
+    output:     Output,
+    positional: Vec<String>,
+    options:    HashMap<String, String>,
+    flags:      Vec<String>,
+}
+
+impl Args
+{
+    fn parse(args: Vec<String>) -> Result<Args, String>
+    {
+        let mut this = Args
+        {
+            server:     std::env::var("MINIQ_URL").unwrap_or_else(|_| String::from("http://localhost:8080")),
+            output:     Output::Table,
+            positional: Vec::new(),
+            options:    HashMap::new(),
+            flags:      Vec::new(),
+        };
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next()
+        {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("\"{}\" requires a value", name));
+
+            match &*arg
+            {
+                "-s" | "--server"                                   => this.server = value(&arg)?,
+                "-o" | "--output"                                   => this.output = match &*value(&arg)?
+                {
+                    "table" => Output::Table,
+                    "json"  => Output::Json,
+                    other   => return Err(format!("unknown output \"{}\", expected table or json", other)),
+                },
+                "--prefix" | "--sort" | "--priority" | "--lifetime" => { let v = value(&arg)?; this.options.insert(arg[2..].to_string(), v); },
+                "--lines" | "--from-start"                          => this.flags.push(arg[2..].to_string()),
+                _ if arg.starts_with('-') && arg != "-"             => return Err(format!("unknown option \"{}\"", arg)),
+                _                                                   => this.positional.push(arg),
+            }
+        }
+
+        Ok(this)
+    }
+
+    fn queue(&self) -> Result<&str, String>
+    {
+        self.positional.get(1).map(String::as_str).ok_or_else(|| format!("\"{}\" requires a queue name", self.positional[0]))
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String>
+    {
+        match self.options.get(name)
+        {
+            Some(v) => v.parse::<T>().map(Some).map_err(|_| format!("\"--{}\" \"{}\" is not a number", name, v)),
+            None    => Ok(None),
+        }
+    }
+}
+
+/// Left-aligned columns separated by two spaces.
+fn _table(headers: &[&str], rows: Vec<Vec<String>>)
+{
+    let mut widths = headers.iter().map(|h| h.len()).collect::<Vec<usize>>();
+
+    for row in &rows
+    {
+        for (i, cell) in row.iter().enumerate() { widths[i] = widths[i].max(cell.chars().count()); }
+    }
+
+    let line = |cells: Vec<String>| cells.iter().enumerate()
+        .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+        .collect::<Vec<String>>().join("  ").trim_end().to_string();
+
+    println!("{}", line(headers.iter().map(|h| h.to_uppercase()).collect()));
+
+    for row in rows { println!("{}", line(row)); }
+}
+
+fn _json<T: serde::Serialize>(value: &T)
+{
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+fn _peer(client: &model::Client) -> String
+{
+    client.identity.clone().unwrap_or_else(|| format!("{}:{}", client.host, client.port))
+}
+
+fn _input(file: Option<&String>) -> Result<String, String>
+{
+    match file
+    {
+        Some(path) if path != "-" => fs::read_to_string(path).map_err(|e| format!("\"{}\": {}", path, e)),
+        _                         =>
+        {
+            let mut buffer = String::new();
+
+            io::stdin().read_to_string(&mut buffer).map_err(|e| format!("stdin: {}", e))?;
+            Ok(buffer)
+        },
+    }
+}
+
+fn list(miniq: &Client, args: &Args) -> Result<(), Error>
+{
+    let mut query  = QueueQuery { prefix: args.options.get("prefix").cloned(), sort: args.options.get("sort").cloned(), ..QueueQuery::default() };
+    let mut queues = Vec::new();
+
+    loop
+    {
+        let page = miniq.queues(&query)?;
+
+        queues.extend(page.queues);
+
+        match page.next
+        {
+            Some(next) => query.cursor = Some(next),
+            None       => break,
+        }
+    }
+
+    match args.output
+    {
+        Output::Json  => _json(&queues),
+        Output::Table => _table(&["queue", "depth", "held", "publishers", "subscribers"], queues.into_iter().map(|q| vec![
+            q.name, q.depth.to_string(), q.held.to_string(), q.publishers.to_string(), q.subscribers.to_string(),
+        ]).collect()),
+    }
+
+    Ok(())
+}
+
+fn show(miniq: &Client, args: &Args, name: &str) -> Result<(), Error>
+{
+    let info = miniq.queue(name)?;
+
+    match args.output
+    {
+        Output::Json  => _json(&info),
+        Output::Table =>
+        {
+            println!("queue:  {}\ndepth:  {}\nheld:   {}\n", info.name, info.depth, info.held);
+
+            let mut rows = info.publishers.iter().map(|c| vec![String::from("publisher"), _peer(c)]).collect::<Vec<Vec<String>>>();
+
+            rows.extend(info.subscribers.iter().map(|c| vec![String::from("subscriber"), _peer(c)]));
+            _table(&["role", "client"], rows);
+        },
+    }
+
+    Ok(())
+}
+
+fn publish(miniq: &Client, args: &Args, name: &str) -> Result<(), String>
+{
+    let lifetime = args.number::<f64>("lifetime")?;
+    let priority = args.number::<usize>("priority")?;
+    let files    = &args.positional[2..];
+    let inputs   = if files.is_empty() { vec![_input(None)?] } else { files.iter().map(|f| _input(Some(f))).collect::<Result<Vec<String>, String>>()? };
+    let messages = if args.flags.iter().any(|f| f == "lines")
+    {
+        inputs.iter().flat_map(|input| input.lines().filter(|line| !line.is_empty()).map(String::from).collect::<Vec<String>>()).collect::<Vec<String>>()
+    } else {
+        inputs
+    };
+
+    let mut sent = Vec::new();
+
+    for data in messages
+    {
+        sent.push(miniq.push(name, &data, lifetime, priority).map_err(|e| e.to_string())?);
+    }
+
+    match args.output
+    {
+        Output::Json  => _json(&sent),
+        Output::Table => _table(&["id", "priority", "bytes"], sent.into_iter().map(|m| vec![m.id.to_string(), m.priority.to_string(), m.data.len().to_string()]).collect()),
+    }
+
+    Ok(())
+}
+
+fn tail(miniq: &Client, args: &Args, name: &str) -> Result<(), Error>
+{
+    let tail = miniq.follow(name)?;
+    let tail = match args.flags.iter().any(|f| f == "from-start")
+    {
+        true  => tail,
+        false => match miniq.queue(name)?.newest_id
+        {
+            Some(id) => tail.after(id),
+            None     => tail,
+        },
+    };
+
+    for message in tail
+    {
+        let message = message?;
+
+        match args.output
+        {
+            Output::Json  => println!("{}", serde_json::to_string(&message).unwrap()),
+            Output::Table => println!("{}  {}  p{}  {}", message.id, model::time::format(&message.created), message.priority, message.data),
+        }
+
+        io::stdout().flush().ok();
+    }
+
+    Ok(())
+}
+
+fn dump(miniq: &Client, args: &Args) -> Result<(), String>
+{
+    let state = serde_json::to_string_pretty(&miniq.dump_state().map_err(|e| e.to_string())?).unwrap();
+
+    match args.positional.get(1)
+    {
+        Some(path) if path != "-" => fs::write(path, state).map_err(|e| format!("\"{}\": {}", path, e)),
+        _                         => { println!("{}", state); Ok(()) },
+    }
+}
+
+fn restore(miniq: &Client, args: &Args) -> Result<(), String>
+{
+    let input    = _input(args.positional.get(1))?;
+    let state    = serde_json::from_str::<serde_json::Value>(&input).map_err(|e| format!("not a dump: {}", e))?;
+    let restored = miniq.restore_state(&state).map_err(|e| e.to_string())?;
+
+    match args.output
+    {
+        Output::Json  => _json(&restored),
+        Output::Table => println!("restored {} queues", restored.restored),
+    }
+
+    Ok(())
+}
+
+fn stats(miniq: &Client, args: &Args) -> Result<(), Error>
+{
+    let info = miniq.info()?;
+
+    match args.output
+    {
+        Output::Json  => _json(&info),
+        Output::Table => _table(&["stat", "value"], vec![
+            vec![String::from("version"),  format!("{} {}", info.name, info.version)],
+            vec![String::from("uptime"),   format!("{:.0}s", info.uptime_seconds)],
+            vec![String::from("queues"),   info.queues.to_string()],
+            vec![String::from("messages"), info.messages.to_string()],
+            vec![String::from("rss"),      info.rss_bytes.map(|b| format!("{:.1} MiB", b as f64 / 1048576.0)).unwrap_or_else(|| String::from("-"))],
+        ]),
+    }
+
+    Ok(())
+}
+
+fn run(args: Args) -> Result<(), String>
+{
+    let miniq = Client::new(&args.server);
+    let err   = |e: Error| e.to_string();
+
+    match args.positional.first().map(String::as_str)
+    {
+        Some("list")    => list(&miniq, &args).map_err(err),
+        Some("create")  => miniq.new_queue(args.queue()?).map(|_| ()).map_err(err),
+        Some("delete")  => miniq.delete_queue(args.queue()?).map(|d| eprintln!("deleted \"{}\" with {} messages", d.name, d.messages)).map_err(err),
+        Some("show")    => show(&miniq, &args, args.queue()?).map_err(err),
+        Some("publish") => publish(&miniq, &args, args.queue()?),
+        Some("tail")    => tail(&miniq, &args, args.queue()?).map_err(err),
+        Some("dump")    => dump(&miniq, &args),
+        Some("restore") => restore(&miniq, &args),
+        Some("stats")   => stats(&miniq, &args).map_err(err),
+        Some(other)     => Err(format!("unknown command \"{}\"", other)),
+        None            => Err(String::from("no command given")),
+    }
+}
+
+fn main()
+{
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    if args.is_empty() || args.iter().any(|arg| arg == "-h" || arg == "--help")
+    {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let result = Args::parse(args).and_then(run);
+
+    if let Err(err) = result
+    {
+        eprintln!("miniqctl: {}", err);
+        process::exit(1);
+    }
+}
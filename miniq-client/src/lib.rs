@@ -0,0 +1,871 @@
+//! Blocking client for the miniq HTTP API.
+//!
+//! ```no_run
+//! let miniq = miniq_client::Client::new("http://localhost:8080");
+//!
+//! miniq.new_queue("jobs").unwrap();
+//! miniq.sub("jobs").unwrap();
+//! miniq.push("jobs", "hello", None, Some(3)).unwrap();
+//!
+//! for message in miniq.consume("jobs").unwrap().take(1)
+//! {
+//!     println!("{}", message.unwrap().data);
+//! }
+//! ```
+
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+
+/// Request and response bodies of every JSON route: the ones the server declares are shared with
+/// it through `miniq-model`, the rest mirror what it writes.
+pub mod model
+{
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    pub use miniq_model::{time, Ack, Call, Deleted, ErrorBody, Grant, Properties, Publisher, Push, QueueName, ReplyQueue, Restored, Role, Staged, StagedAck, StagedPush, Subscribe, TransactionId};
+
+    /// A connected peer: its address and, over mutual TLS, its certificate identity.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Client
+    {
+        pub host:     String,
+        pub port:     u64,
+        pub identity: Option<String>,
+    }
+
+    /// Permissions by principal, e.g. `{"*": ["subscribe", "read_metadata"]}`.
+    pub type Acl = HashMap<String, Vec<String>>;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Message
+    {
+        pub id:         u64,
+        pub sender:     Client,
+        pub recipients: Vec<Client>,
+        #[serde(with = "miniq_model::time")]
+        pub created:    SystemTime,
+        /// When the message expires.
+        #[serde(with = "miniq_model::time")]
+        pub lifetime:   SystemTime,
+        pub data:       String,
+        pub active:     bool,
+        pub priority:   usize,
+        #[serde(flatten)]
+        pub properties: Properties,
+    }
+
+    /// A queue with all of its messages, as returned by `/` and `/new_queue`.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Queue
+    {
+        pub name:        String,
+        #[serde(rename = "publisher")]
+        pub publishers:  Vec<Client>,
+        pub subscribers: Vec<Client>,
+        /// Selector of each subscriber that has one, by `host:port` or identity.
+        #[serde(default)]
+        pub selectors:   HashMap<String, String>,
+        pub data:        Vec<Message>,
+        pub acl:         Acl,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct QueueSummary
+    {
+        pub name:        String,
+        pub depth:       usize,
+        pub held:        usize,
+        pub publishers:  usize,
+        pub subscribers: usize,
+        /// Approximate bytes its messages take in memory.
+        pub bytes:       usize,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct QueuePage
+    {
+        pub queues: Vec<QueueSummary>,
+        pub total:  usize,
+        pub next:   Option<usize>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct QueueInfo
+    {
+        pub name:        String,
+        pub depth:       usize,
+        pub held:        usize,
+        pub publishers:  Vec<Client>,
+        pub subscribers: Vec<Client>,
+        pub oldest_id:   Option<u64>,
+        pub newest_id:   Option<u64>,
+        pub acl:         Acl,
+        pub bytes:       usize,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct MessagePage
+    {
+        pub queue:    String,
+        pub messages: Vec<Message>,
+        pub next:     Option<u64>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct AuditEntry
+    {
+        pub id:     u64,
+        pub at:     String,
+        pub action: String,
+        pub queue:  String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct AuditPage
+    {
+        pub principal: String,
+        pub entries:   Vec<AuditEntry>,
+        pub next:      Option<u64>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Health
+    {
+        pub status: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Readiness
+    {
+        pub status:    String,
+        pub loaded:    bool,
+        pub listening: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Info
+    {
+        pub name:           String,
+        pub version:        String,
+        pub uptime_seconds: f64,
+        pub features:       HashMap<String, bool>,
+        pub queues:         usize,
+        pub messages:       usize,
+        pub rss_bytes:      Option<u64>,
+        pub memory:         Memory,
+    }
+
+    /// Approximate bytes all queues hold, and the limit past which flow control starts.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Memory
+    {
+        pub used_bytes:  u64,
+        pub limit_bytes: Option<u64>,
+    }
+
+    /// Answer of `/transaction/commit`, each list in staging order.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Outcome
+    {
+        pub published: Vec<Message>,
+        pub acked:     Vec<Message>,
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error
+{
+    /// The server answered with an error; `code` is its machine-readable name, e.g. `not_found`.
+    Api { status: u16, code: String, message: String },
+    /// The request never got an answer.
+    Transport(String),
+    /// The answer was not the JSON we expected.
+    Decode(String),
+}
+
+impl Error
+{
+    pub fn code(&self) -> Option<&str>
+    {
+        match self
+        {
+            Error::Api { code, .. } => Some(code),
+            _                       => None,
+        }
+    }
+
+    fn _retryable(&self) -> bool
+    {
+        match self
+        {
+            Error::Api { status, .. } => *status == 429 || *status == 503,
+            Error::Transport(_)       => true,
+            Error::Decode(_)          => false,
+        }
+    }
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Error::Api { status, code, message } => write!(f, "{} {}: {}", status, code, message),
+            Error::Transport(message)            => write!(f, "transport: {}", message),
+            Error::Decode(message)               => write!(f, "decode: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ureq::Error> for Error
+{
+    fn from(err: ureq::Error) -> Self
+    {
+        match err
+        {
+            ureq::Error::Status(status, response) =>
+            {
+                let body = response.into_string().unwrap_or_default();
+
+                match serde_json::from_str::<model::ErrorBody>(&body)
+                {
+                    Ok(body) => Error::Api { status, code: body.code, message: body.error },
+                    Err(_)   => Error::Api { status, code: String::from("unknown"), message: body },
+                }
+            },
+            ureq::Error::Transport(transport) => Error::Transport(transport.to_string()),
+        }
+    }
+}
+
+
+/// Filters for `Client::queues`; see `/queues`.
+#[derive(Debug, Clone, Default)]
+pub struct QueueQuery
+{
+    pub prefix: Option<String>,
+    /// `name` or `depth`.
+    pub sort:   Option<String>,
+    /// `asc` or `desc`.
+    pub order:  Option<String>,
+    pub cursor: Option<usize>,
+    pub limit:  Option<usize>,
+}
+
+/// Filters for `Client::messages`; times are unix seconds or RFC 3339.
+#[derive(Debug, Clone, Default)]
+pub struct MessageQuery
+{
+    pub cursor:       Option<u64>,
+    pub sender:       Option<String>,
+    pub priority:     Option<usize>,
+    pub min_priority: Option<usize>,
+    pub active:       Option<bool>,
+    pub since:        Option<String>,
+    pub until:        Option<String>,
+    pub limit:        Option<usize>,
+}
+
+/// Filters for `Client::user_log`; times are unix seconds or RFC 3339.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery
+{
+    pub principal: Option<String>,
+    pub action:    Option<String>,
+    pub queue:     Option<String>,
+    pub since:     Option<String>,
+    pub until:     Option<String>,
+    pub cursor:    Option<u64>,
+    pub limit:     Option<usize>,
+}
+
+fn _params(pairs: Vec<(&'static str, Option<String>)>) -> Vec<(&'static str, String)>
+{
+    pairs.into_iter().filter_map(|(name, value)| value.map(|value| (name, value))).collect()
+}
+
+impl QueueQuery
+{
+    fn params(&self) -> Vec<(&'static str, String)>
+    {
+        _params(vec![
+            ("prefix", self.prefix.clone()),
+            ("sort",   self.sort.clone()),
+            ("order",  self.order.clone()),
+            ("cursor", self.cursor.map(|v| v.to_string())),
+            ("limit",  self.limit.map(|v| v.to_string())),
+        ])
+    }
+}
+
+impl MessageQuery
+{
+    fn params(&self) -> Vec<(&'static str, String)>
+    {
+        _params(vec![
+            ("cursor",       self.cursor.map(|v| v.to_string())),
+            ("sender",       self.sender.clone()),
+            ("priority",     self.priority.map(|v| v.to_string())),
+            ("min_priority", self.min_priority.map(|v| v.to_string())),
+            ("active",       self.active.map(|v| v.to_string())),
+            ("since",        self.since.clone()),
+            ("until",        self.until.clone()),
+            ("limit",        self.limit.map(|v| v.to_string())),
+        ])
+    }
+}
+
+impl AuditQuery
+{
+    fn params(&self) -> Vec<(&'static str, String)>
+    {
+        _params(vec![
+            ("principal", self.principal.clone()),
+            ("action",    self.action.clone()),
+            ("queue",     self.queue.clone()),
+            ("since",     self.since.clone()),
+            ("until",     self.until.clone()),
+            ("cursor",    self.cursor.map(|v| v.to_string())),
+            ("limit",     self.limit.map(|v| v.to_string())),
+        ])
+    }
+}
+
+/// A `/push` body for queue `name`.
+fn _push(name: &str, data: &str, lifetime: Option<f64>, priority: Option<usize>, properties: model::Properties) -> model::Push
+{
+    model::Push { name: String::from(name), message: miniq_model::Message { data: String::from(data), lifetime, priority, properties } }
+}
+
+
+/// Connection to one miniq server.
+///
+/// Transport failures, `429 queue_full` and `503 unavailable` are retried with exponential backoff;
+/// a retried `push` may therefore be delivered twice if the first answer was lost.
+#[derive(Debug, Clone)]
+pub struct Client
+{
+    base:    String,
+    agent:   ureq::Agent,
+    retries: u32,
+    backoff: Duration,
+    poll:    Duration,
+}
+
+impl Client
+{
+    /// `base` is the server root, e.g. `http://localhost:8080`.
+    pub fn new(base: &str) -> Client
+    {
+        Client
+        {
+            base:    String::from(base.trim_end_matches('/')),
+            agent:   ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build(),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            poll:    Duration::from_millis(500),
+        }
+    }
+
+    /// Extra attempts after the first one; `0` disables retries.
+    pub fn retries(mut self, retries: u32) -> Client
+    {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait before the first retry; doubles on every further one.
+    pub fn backoff(mut self, backoff: Duration) -> Client
+    {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Client
+    {
+        self.agent = ureq::AgentBuilder::new().timeout(timeout).build();
+        self
+    }
+
+    /// How long a `Consumer` or `Tail` sleeps when the queue has nothing new.
+    pub fn poll_interval(mut self, poll: Duration) -> Client
+    {
+        self.poll = poll;
+        self
+    }
+
+    fn _send<F: Fn() -> Result<ureq::Response, Box<ureq::Error>>>(&self, request: F) -> Result<ureq::Response, Error>
+    {
+        let mut attempt = 0;
+
+        loop
+        {
+            let err = match request()
+            {
+                Ok(response) => return Ok(response),
+                Err(err)     => Error::from(*err),
+            };
+
+            if attempt >= self.retries || !err._retryable() { return Err(err); }
+
+            thread::sleep(self.backoff * 2u32.saturating_pow(attempt));
+            attempt += 1;
+        }
+    }
+
+    fn _decode<T: DeserializeOwned>(response: ureq::Response) -> Result<T, Error>
+    {
+        response.into_json::<T>().map_err(|e| Error::Decode(e.to_string()))
+    }
+
+    fn post<B: Serialize, T: DeserializeOwned>(&self, route: &str, body: &B) -> Result<T, Error>
+    {
+        let url  = format!("{}{}", self.base, route);
+        let body = serde_json::to_value(body).map_err(|e| Error::Decode(e.to_string()))?;
+
+        Self::_decode(self._send(|| self.agent.post(&url).send_json(body.clone()).map_err(Box::new))?)
+    }
+
+    fn get<T: DeserializeOwned>(&self, route: &str, params: &[(&'static str, String)]) -> Result<T, Error>
+    {
+        let url = format!("{}{}", self.base, route);
+
+        Self::_decode(self._send(||
+        {
+            params.iter().fold(self.agent.get(&url), |request, (name, value)| request.query(name, value)).call().map_err(Box::new)
+        })?)
+    }
+
+    /// Creates `name`; answers with every queue the caller can see.
+    pub fn new_queue(&self, name: &str) -> Result<std::collections::HashMap<String, model::Queue>, Error>
+    {
+        self.post("/new_queue", &model::QueueName { name: String::from(name) })
+    }
+
+    /// Removes `name` and its messages; answers with how many messages went with it.
+    pub fn delete_queue(&self, name: &str) -> Result<model::Deleted, Error>
+    {
+        self.post("/delete_queue", &model::QueueName { name: String::from(name) })
+    }
+
+    /// Every queue in the server's snapshot format, `{"queues": [...]}`; operators only.
+    pub fn dump_state(&self) -> Result<serde_json::Value, Error>
+    {
+        self.get("/state", &[])
+    }
+
+    /// Loads a `dump_state` body, replacing queues of the same name; operators only.
+    pub fn restore_state(&self, state: &serde_json::Value) -> Result<model::Restored, Error>
+    {
+        self.post("/state", state)
+    }
+
+    /// Every queue the caller can see, with all of its messages.
+    pub fn full_map(&self) -> Result<std::collections::HashMap<String, model::Queue>, Error>
+    {
+        self.get("/", &[])
+    }
+
+    /// Subscribes the caller; answers with the subscribers.
+    pub fn sub(&self, name: &str) -> Result<Vec<model::Client>, Error>
+    {
+        self.post("/sub", &model::QueueName { name: String::from(name) })
+    }
+
+    /// Subscribes the caller to the messages of `name` that `selector` matches, e.g.
+    /// `region = 'eu' AND priority > 3` over the message headers and `priority`.
+    pub fn sub_where(&self, name: &str, selector: &str) -> Result<Vec<model::Client>, Error>
+    {
+        self.post("/sub", &model::Subscribe { name: String::from(name), selector: Some(String::from(selector)) })
+    }
+
+    pub fn unsub(&self, name: &str) -> Result<Vec<model::Client>, Error>
+    {
+        self.post("/unsub", &model::QueueName { name: String::from(name) })
+    }
+
+    /// Adds `client` (the caller when `None`) as a publisher; answers with the publishers.
+    pub fn add_publisher(&self, name: &str, client: Option<&str>) -> Result<Vec<model::Client>, Error>
+    {
+        self.post("/pub", &model::Publisher { name: String::from(name), client: client.map(String::from) })
+    }
+
+    pub fn remove_publisher(&self, name: &str, client: Option<&str>) -> Result<Vec<model::Client>, Error>
+    {
+        self.post("/unpub", &model::Publisher { name: String::from(name), client: client.map(String::from) })
+    }
+
+    /// `lifetime` is in seconds; the server default applies when `None`.
+    pub fn push(&self, name: &str, data: &str, lifetime: Option<f64>, priority: Option<usize>) -> Result<model::Message, Error>
+    {
+        self.post("/push", &_push(name, data, lifetime, priority, model::Properties::default()))
+    }
+
+    /// `push` that is safe to retry: the server answers the original message for a `dedup_id` it
+    /// has seen within `queue.dedup_window`, or a `duplicate` error code with `queue.dedup = "reject"`.
+    pub fn push_once(&self, name: &str, data: &str, dedup_id: &str, lifetime: Option<f64>, priority: Option<usize>) -> Result<model::Message, Error>
+    {
+        self.post("/push", &_push(name, data, lifetime, priority, model::Properties { dedup_id: Some(String::from(dedup_id)), ..model::Properties::default() }))
+    }
+
+    /// `push` into the message group `group_id`: its messages go to one subscriber at a time, in
+    /// the order they were pushed.
+    pub fn push_in_group(&self, name: &str, data: &str, group_id: &str, lifetime: Option<f64>, priority: Option<usize>) -> Result<model::Message, Error>
+    {
+        self.post("/push", &_push(name, data, lifetime, priority, model::Properties { group_id: Some(String::from(group_id)), ..model::Properties::default() }))
+    }
+
+    /// Publishes a `/push` body as it is, e.g. with `headers` set.
+    pub fn publish(&self, push: &model::Push) -> Result<model::Message, Error>
+    {
+        self.post("/push", push)
+    }
+
+    /// Answers `request` on its `reply_to` queue, carrying over its `correlation_id`.
+    pub fn reply(&self, request: &model::Message, data: &str) -> Result<model::Message, Error>
+    {
+        let reply_to = request.properties.reply_to.clone().ok_or_else(|| Error::Decode(format!("message {} has no reply_to", request.id)))?;
+
+        self.post("/push", &_push(&reply_to, data, None, None, model::Properties { correlation_id: request.properties.correlation_id.clone(), ..model::Properties::default() }))
+    }
+
+    /// Creates an exclusive reply queue and answers its name; it is dropped once not read for
+    /// `idle`, the server's `queue.reply_timeout` when `None`.
+    pub fn reply_queue(&self, idle: Option<Duration>) -> Result<String, Error>
+    {
+        self.post::<_, model::QueueName>("/reply_queue", &model::ReplyQueue { idle_timeout: idle.map(|d| d.as_secs_f64()) }).map(|queue| queue.name)
+    }
+
+    /// Publishes `data` to `name` and blocks until a consumer `reply`s to it, for at most `timeout`
+    /// (the server's `queue.reply_timeout` when `None`); a `timeout` error code if none does.
+    /// Never retried, so a request is published at most once.
+    pub fn request(&self, name: &str, data: &str, timeout: Option<Duration>) -> Result<model::Message, Error>
+    {
+        let url  = format!("{}/request", self.base);
+        let body = model::Call { name: String::from(name), data: String::from(data), lifetime: None, priority: None, timeout: timeout.map(|d| d.as_secs_f64()) };
+        let wait = timeout.unwrap_or(Duration::from_secs(30)) + Duration::from_secs(5);
+
+        Self::_decode(self.agent.post(&url).timeout(wait).send_json(body).map_err(Error::from)?)
+    }
+
+    pub fn grant(&self, name: &str, principal: &str, permission: &str) -> Result<model::Acl, Error>
+    {
+        self.post("/grant", &model::Grant { name: String::from(name), principal: String::from(principal), permission: String::from(permission) })
+    }
+
+    pub fn revoke(&self, name: &str, principal: &str, permission: &str) -> Result<model::Acl, Error>
+    {
+        self.post("/revoke", &model::Grant { name: String::from(name), principal: String::from(principal), permission: String::from(permission) })
+    }
+
+    /// Gives `principal` a role; answers with all of its roles.
+    pub fn assign_role(&self, principal: &str, role: &str) -> Result<Vec<String>, Error>
+    {
+        self.post("/role", &model::Role { principal: String::from(principal), role: String::from(role) })
+    }
+
+    pub fn user_log(&self, query: &AuditQuery) -> Result<model::AuditPage, Error>
+    {
+        self.get("/user_log", &query.params())
+    }
+
+    pub fn queues(&self, query: &QueueQuery) -> Result<model::QueuePage, Error>
+    {
+        self.get("/queues", &query.params())
+    }
+
+    pub fn queue(&self, name: &str) -> Result<model::QueueInfo, Error>
+    {
+        self.get("/queue", &[("name", String::from(name))])
+    }
+
+    /// One page of messages, oldest first; nothing is consumed.
+    pub fn messages(&self, name: &str, query: &MessageQuery) -> Result<model::MessagePage, Error>
+    {
+        let mut params = query.params();
+
+        params.push(("name", String::from(name)));
+        self.get("/messages", &params)
+    }
+
+    /// The payload of message `id` in `name` on its own, read as it arrives; for payloads too
+    /// large to page through with `messages`.
+    pub fn payload(&self, name: &str, id: u64) -> Result<Box<dyn std::io::Read + Send + Sync>, Error>
+    {
+        let url = format!("{}/v2/queues/{}/messages/{}/data", self.base, name, id);
+
+        Ok(self._send(|| self.agent.get(&url).call().map_err(Box::new))?.into_reader())
+    }
+
+    pub fn healthz(&self) -> Result<model::Health, Error>
+    {
+        self.get("/healthz", &[])
+    }
+
+    pub fn readyz(&self) -> Result<model::Readiness, Error>
+    {
+        self.get("/readyz", &[])
+    }
+
+    pub fn info(&self) -> Result<model::Info, Error>
+    {
+        self.get("/info", &[])
+    }
+
+    /// Prometheus text exposition of `/metrics`.
+    pub fn metrics(&self) -> Result<String, Error>
+    {
+        let url = format!("{}/metrics", self.base);
+
+        self._send(|| self.agent.get(&url).call().map_err(Box::new))?.into_string().map_err(|e| Error::Decode(e.to_string()))
+    }
+
+    /// Begins a transaction: the pushes and acks staged on it are applied together on `commit`,
+    /// or not at all.
+    pub fn transaction(&self) -> Result<Transaction<'_>, Error>
+    {
+        self.post::<_, model::TransactionId>("/transaction", &serde_json::json!({})).map(|id| Transaction { client: self, id: id.transaction })
+    }
+
+    /// Takes the next message of `name` waiting for the caller, who must be subscribed; `None`
+    /// when there is none yet. A retried call may lose a message whose answer was lost.
+    pub fn take(&self, name: &str) -> Result<Option<model::Message>, Error>
+    {
+        self.post("/consume", &model::QueueName { name: String::from(name) })
+    }
+
+    /// Takes message `id` of `name` off the queue for the caller, whatever its priority.
+    pub fn ack(&self, name: &str, id: u64) -> Result<model::Message, Error>
+    {
+        self.post("/ack", &model::Ack { name: String::from(name), id })
+    }
+
+    /// Iterates the messages of `name` waiting for the caller, taking each off the queue for them
+    /// and blocking for new ones. It does not subscribe: call `sub` or `sub_where` first.
+    pub fn consume(&self, name: &str) -> Result<Consumer<'_>, Error>
+    {
+        Ok(Consumer { client: self, queue: String::from(name) })
+    }
+
+    /// Iterates the active messages of `name`, blocking for new ones. It only reads them, so it
+    /// neither subscribes nor takes anything off the queue.
+    pub fn follow(&self, name: &str) -> Result<Tail<'_>, Error>
+    {
+        Ok(Tail { client: self, queue: String::from(name), cursor: None, buffered: VecDeque::new() })
+    }
+}
+
+
+/// An open transaction; dropping it without `commit` or `rollback` leaves it to the server's
+/// `queue.transaction_timeout`.
+pub struct Transaction<'a>
+{
+    client: &'a Client,
+    id:     String,
+}
+
+impl<'a> Transaction<'a>
+{
+    pub fn id(&self) -> &str
+    {
+        &self.id
+    }
+
+    /// Stages a `push`; answers how many operations the transaction holds.
+    pub fn push(&self, name: &str, data: &str, lifetime: Option<f64>, priority: Option<usize>) -> Result<usize, Error>
+    {
+        let push = _push(name, data, lifetime, priority, model::Properties::default());
+
+        self.client.post::<_, model::Staged>("/transaction/push", &model::StagedPush { transaction: self.id.clone(), push }).map(|staged| staged.operations)
+    }
+
+    /// Stages taking message `id` of `name` off the queue for the caller.
+    pub fn ack(&self, name: &str, id: u64) -> Result<usize, Error>
+    {
+        let ack = model::Ack { name: String::from(name), id };
+
+        self.client.post::<_, model::Staged>("/transaction/ack", &model::StagedAck { transaction: self.id.clone(), ack }).map(|staged| staged.operations)
+    }
+
+    /// Applies every staged operation, or none of them if one fails.
+    pub fn commit(self) -> Result<model::Outcome, Error>
+    {
+        self.client.post("/transaction/commit", &model::TransactionId { transaction: self.id })
+    }
+
+    /// Discards the transaction; answers how many operations it held.
+    pub fn rollback(self) -> Result<usize, Error>
+    {
+        self.client.post::<_, model::Staged>("/transaction/rollback", &model::TransactionId { transaction: self.id }).map(|staged| staged.operations)
+    }
+}
+
+
+/// Blocking iterator taking a queue's messages for a subscriber, highest priority first, through
+/// `/consume`; message groups come in publish order.
+pub struct Consumer<'a>
+{
+    client: &'a Client,
+    queue:  String,
+}
+
+impl<'a> Iterator for Consumer<'a>
+{
+    type Item = Result<model::Message, Error>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        loop
+        {
+            match self.client.take(&self.queue)
+            {
+                Ok(Some(message)) => return Some(Ok(message)),
+                Ok(None)          => thread::sleep(self.client.poll),
+                Err(err)          => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+
+/// Blocking iterator over a queue's messages, each yielded once, in id order.
+///
+/// Follows `/messages` with its cursor, so messages are read rather than taken off the queue.
+pub struct Tail<'a>
+{
+    client:   &'a Client,
+    queue:    String,
+    cursor:   Option<u64>,
+    buffered: VecDeque<model::Message>,
+}
+
+impl<'a> Tail<'a>
+{
+    /// Skips every message up to and including `id`.
+    pub fn after(mut self, id: u64) -> Tail<'a>
+    {
+        self.cursor = Some(id);
+        self
+    }
+}
+
+impl<'a> Iterator for Tail<'a>
+{
+    type Item = Result<model::Message, Error>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        loop
+        {
+            if let Some(message) = self.buffered.pop_front() { return Some(Ok(message)); }
+
+            let query = MessageQuery { cursor: self.cursor, active: Some(true), ..MessageQuery::default() };
+
+            match self.client.messages(&self.queue, &query)
+            {
+                Ok(page) =>
+                {
+                    match page.messages.last()
+                    {
+                        Some(last) => self.cursor = Some(last.id),
+                        None       => thread::sleep(self.client.poll),
+                    }
+
+                    self.buffered.extend(page.messages);
+                },
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::cell::Cell;
+
+    fn _answer(status: u16, body: &str) -> Result<ureq::Response, Box<ureq::Error>>
+    {
+        let response = ureq::Response::new(status, "", body).unwrap();
+
+        if status < 400 { Ok(response) } else { Err(Box::new(ureq::Error::Status(status, response))) }
+    }
+
+    fn _client(retries: u32) -> Client
+    {
+        Client::new("http://localhost:8080/").retries(retries).backoff(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn api_errors_carry_the_server_code()
+    {
+        let err = Error::from(*_answer(404, r#"{"error":"queue \"a\" not found","code":"not_found"}"#).unwrap_err());
+
+        assert_eq!(err, Error::Api { status: 404, code: String::from("not_found"), message: String::from("queue \"a\" not found") });
+        assert_eq!(err.code(), Some("not_found"));
+        assert_eq!(err.to_string(), "404 not_found: queue \"a\" not found");
+        assert_eq!(Error::from(*_answer(502, "bad gateway").unwrap_err()).code(), Some("unknown"));
+    }
+
+    #[test]
+    fn busy_answers_are_retried()
+    {
+        let attempts = Cell::new(0);
+        let response = _client(3)._send(||
+        {
+            attempts.set(attempts.get() + 1);
+
+            if attempts.get() < 3 { _answer(503, r#"{"error":"busy","code":"unavailable"}"#) } else { _answer(200, "{}") }
+        });
+
+        assert!(response.is_ok());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn retries_stop()
+    {
+        let attempts = Cell::new(0);
+        let err      = _client(2)._send(|| { attempts.set(attempts.get() + 1); _answer(429, r#"{"error":"full","code":"queue_full"}"#) }).unwrap_err();
+
+        assert_eq!((err.code(), attempts.get()), (Some("queue_full"), 3));
+
+        attempts.set(0);
+
+        let err = _client(2)._send(|| { attempts.set(attempts.get() + 1); _answer(409, r#"{"error":"taken","code":"already_exists"}"#) }).unwrap_err();
+
+        assert_eq!((err.code(), attempts.get()), (Some("already_exists"), 1));
+    }
+
+    #[test]
+    fn queries_send_only_what_is_set()
+    {
+        let query = MessageQuery { cursor: Some(7), active: Some(true), since: Some(String::from("0")), ..MessageQuery::default() };
+
+        assert_eq!(query.params(), vec![("cursor", String::from("7")), ("active", String::from("true")), ("since", String::from("0"))]);
+        assert_eq!(QueueQuery::default().params(), Vec::new());
+        assert_eq!(AuditQuery { limit: Some(5), ..AuditQuery::default() }.params(), vec![("limit", String::from("5"))]);
+    }
+
+    #[test]
+    fn bases_lose_their_trailing_slash()
+    {
+        assert_eq!(_client(0).base, "http://localhost:8080");
+    }
+}
@@ -0,0 +1,303 @@
+//! Request and answer bodies of the miniq JSON routes, shared by the server and `miniq-client`.
+//!
+//! The server reads request bodies with the deserializers below, which reject values the broker
+//! cannot use; clients write them with `None` and empty fields left out. With the `schema` feature
+//! every type also describes itself as a JSON schema, for the server's OpenAPI document.
+
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::BTreeMap;
+
+use serde::de::{self, Deserialize, Deserializer};
+
+/// How message times are written on the wire.
+pub const TIME_FORMAT:  &str = "%d/%m/%Y %T";
+/// Longest accepted `lifetime`, in seconds.
+pub const MAX_LIFETIME: f64  = u32::MAX as f64;
+
+/// Message times as `TIME_FORMAT` in UTC, for `#[serde(with = "time")]`.
+pub mod time
+{
+    use std::time::SystemTime;
+
+    use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::Serializer;
+
+    pub fn format(time: &SystemTime) -> String
+    {
+        let time: DateTime<Utc> = (*time).into();
+
+        time.format(super::TIME_FORMAT).to_string()
+    }
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        serializer.serialize_str(&format(time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error>
+    {
+        let text = String::deserialize(deserializer)?;
+        let time = NaiveDateTime::parse_from_str(&text, super::TIME_FORMAT).map_err(|e| de::Error::custom(format!("\"{}\": {}", text, e)))?;
+
+        Ok(SystemTime::from(Utc.from_utc_datetime(&time)))
+    }
+}
+
+/// Absent or `null` is `None`; anything but a non-negative integer is rejected.
+pub fn non_negative_integer<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error>
+{
+    match Option::<serde_json::Value>::deserialize(deserializer)?
+    {
+        None        => Ok(None),
+        Some(value) => value.as_u64().map(|n| Some(n as usize)).ok_or_else(|| de::Error::custom("must be a non-negative integer")),
+    }
+}
+
+/// Absent or `null` is `None`; anything but a number in `(0, MAX_LIFETIME]` is rejected.
+pub fn positive_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error>
+{
+    match Option::<serde_json::Value>::deserialize(deserializer)?
+    {
+        None        => Ok(None),
+        Some(value) => match value.as_f64()
+        {
+            Some(n) if n > 0.0 && n <= MAX_LIFETIME => Ok(Some(n)),
+            Some(_)                                 => Err(de::Error::custom(format!("must be a positive number of at most {}", MAX_LIFETIME))),
+            None                                    => Err(de::Error::custom("must be a positive number")),
+        },
+    }
+}
+
+/// Message payloads: a string as it is, any other JSON value in its serialized form.
+pub fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error>
+{
+    match serde_json::Value::deserialize(deserializer)?
+    {
+        serde_json::Value::String(text) => Ok(text),
+        serde_json::Value::Null         => Err(de::Error::custom("is required")),
+        other                           => Ok(other.to_string()),
+    }
+}
+
+/// An object of strings, numbers and booleans, the latter two in their serialized form.
+pub fn headers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+{
+    BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?.into_iter().map(|(name, value)| match value
+    {
+        serde_json::Value::String(text)                           => Ok((name, text)),
+        serde_json::Value::Number(_) | serde_json::Value::Bool(_) => Ok((name, value.to_string())),
+        _                                                         => Err(de::Error::custom(format!("\"{}\" must be a string, number or boolean", name))),
+    }).collect()
+}
+
+/// Optional attributes a publisher sets on a message, carried through to consumers untouched.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Properties
+{
+    /// Queue the consumer should answer on, usually a reply queue of the requester.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to:       Option<String>,
+    /// Copied from a request onto its reply so the requester can match them up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Idempotency key: a second publish with it inside `queue.dedup_window` is a duplicate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_id:       Option<String>,
+    /// Ordering key: messages sharing it go to one subscriber at a time, in publish order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id:       Option<String>,
+    /// Free-form attributes subscription selectors can test; numbers and booleans are kept as text.
+    #[serde(default, deserialize_with = "headers", skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers:        BTreeMap<String, String>,
+}
+
+/// Body of `/new_queue`, `/unsub` and `/delete_queue`; answer of `/reply_queue`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct QueueName
+{
+    pub name: String,
+}
+
+/// Body of `/sub`: `selector` limits the messages addressed to the caller.
+// The server reads `selector` parsed, clients write it as text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "S: Deserialize<'de>"))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(rename = "Subscribe"))]
+pub struct Subscribe<S = String>
+{
+    pub name:     String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<S>,
+}
+
+/// Body of `/pub` and `/unpub`: `client` is `host:port` or a certificate identity; the caller when absent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Publisher
+{
+    pub name:   String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+}
+
+/// Body of `POST /v2/queues/{name}/messages`: a message as its publisher writes it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Message
+{
+    /// Stored as it is when a string, serialized otherwise.
+    #[serde(deserialize_with = "text")]
+    pub data:       String,
+    /// Seconds until the message expires; the configured default when absent.
+    #[serde(default, deserialize_with = "positive_number", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0, max = 4294967295.0)))]
+    pub lifetime:   Option<f64>,
+    #[serde(default, deserialize_with = "non_negative_integer", skip_serializing_if = "Option::is_none")]
+    pub priority:   Option<usize>,
+    #[serde(flatten)]
+    pub properties: Properties,
+}
+
+/// Body of `/push`: a `Message` for queue `name`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Push
+{
+    pub name:    String,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+/// Body of `/reply_queue` and `POST /v2/reply_queues`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ReplyQueue
+{
+    /// Seconds the queue lives without its creator reading it; `queue.reply_timeout` when absent.
+    #[serde(default, deserialize_with = "positive_number", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0, max = 4294967295.0)))]
+    pub idle_timeout: Option<f64>,
+}
+
+/// Body of `/request`: a message whose `reply_to` and `correlation_id` the broker sets.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Call
+{
+    pub name:     String,
+    /// Stored as it is when a string, serialized otherwise.
+    #[serde(deserialize_with = "text")]
+    pub data:     String,
+    /// Seconds until the request expires; the configured default when absent.
+    #[serde(default, deserialize_with = "positive_number", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0, max = 4294967295.0)))]
+    pub lifetime: Option<f64>,
+    #[serde(default, deserialize_with = "non_negative_integer", skip_serializing_if = "Option::is_none")]
+    pub priority: Option<usize>,
+    /// Seconds to wait for the reply; `queue.reply_timeout` when absent or longer.
+    #[serde(default, deserialize_with = "positive_number", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0, max = 4294967295.0)))]
+    pub timeout:  Option<f64>,
+}
+
+/// Answer of `/transaction` and `POST /v2/transactions`; body of `/transaction/commit` and `/transaction/rollback`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TransactionId
+{
+    pub transaction: String,
+}
+
+/// Body of `/transaction/push`: a `/push` body, staged in `transaction`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StagedPush
+{
+    pub transaction: String,
+    #[serde(flatten)]
+    pub push:        Push,
+}
+
+/// Body of `/ack` and `POST /v2/transactions/{id}/acks`: message `id` of queue `name`, taken off the queue for the caller.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Ack
+{
+    pub name: String,
+    pub id:   u64,
+}
+
+/// Body of `/transaction/ack`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StagedAck
+{
+    pub transaction: String,
+    #[serde(flatten)]
+    pub ack:         Ack,
+}
+
+/// Answer of staging in a transaction, and of rolling it back: how many operations it holds, or held.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Staged
+{
+    pub transaction: String,
+    pub operations:  usize,
+}
+
+/// Body of `/grant` and `/revoke`.
+// The server reads `permission` parsed, clients write it as its name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(rename = "Grant"))]
+pub struct Grant<P = String>
+{
+    pub name:       String,
+    pub principal:  String,
+    pub permission: P,
+}
+
+/// Body of `/role`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Role
+{
+    pub principal: String,
+    pub role:      String,
+}
+
+/// Body of every error answer; `fields` only comes with `invalid` request bodies, `original_id`
+/// only with `duplicate` publishes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ErrorBody
+{
+    pub error:       String,
+    pub code:        String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields:      Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_id: Option<u64>,
+}
+
+/// Answer of `/delete_queue`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Deleted
+{
+    pub name:     String,
+    pub messages: usize,
+}
+
+/// Answer of `POST /state`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Restored
+{
+    pub restored: usize,
+}
@@ -0,0 +1,405 @@
+use super::*;
+
+/// Grant key matching every client.
+pub const ANYONE:      &str = "*";
+/// Grant keys starting with this prefix name a role instead of a client.
+pub const ROLE_PREFIX: &str = "role:";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission
+{
+    Create,
+    Publish,
+    Subscribe,
+    Manage,
+    ReadMetadata,
+}
+
+impl Permission
+{
+    pub fn all() -> Vec<Permission>
+    {
+        vec![Permission::Create, Permission::Publish, Permission::Subscribe, Permission::Manage, Permission::ReadMetadata]
+    }
+}
+
+impl std::str::FromStr for Permission
+{
+    type Err = QueueError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name
+        {
+            "create"        => Ok(Permission::Create),
+            "publish"       => Ok(Permission::Publish),
+            "subscribe"     => Ok(Permission::Subscribe),
+            "manage"        => Ok(Permission::Manage),
+            "read_metadata" => Ok(Permission::ReadMetadata),
+            _               => Err(QueueError::Invalid(format!("\"{}\" is not a permission", name))),
+        }
+    }
+}
+
+impl fmt::Display for Permission
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Permission::Create       => "create",
+            Permission::Publish      => "publish",
+            Permission::Subscribe    => "subscribe",
+            Permission::Manage       => "manage",
+            Permission::ReadMetadata => "read_metadata",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Written as its name, e.g. `"read_metadata"`.
+impl serde::Serialize for Permission
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// Read from its name; an unknown one is reported against the field holding it.
+impl<'de> serde::Deserialize<'de> for Permission
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        String::deserialize(deserializer)?.parse::<Permission>().map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for Permission
+{
+    fn schema_name() -> String
+    {
+        String::from("Permission")
+    }
+
+    fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema
+    {
+        schemars::schema::SchemaObject
+        {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            enum_values:   Some(Permission::all().iter().map(|p| p.to_string().into()).collect()),
+            ..Default::default()
+        }.into()
+    }
+}
+
+/// Permissions granted to principals: a certificate identity, `host:port`, a host under
+/// `auth.host_principals`, `role:<name>` or `*`.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Acl
+{
+    grants: HashMap<String, Vec<Permission>>,
+}
+
+impl Default for Acl
+{
+    fn default() -> Acl
+    {
+        Acl::new()
+    }
+}
+
+impl Acl
+{
+    pub fn new() -> Acl
+    {
+        Acl { grants: HashMap::new() }
+    }
+
+    /// Default for a new queue: its creator may do everything, anyone may subscribe and read metadata.
+    pub fn for_queue(creator: &Client) -> Acl
+    {
+        let mut this = Acl::new();
+
+        for permission in Permission::all() { this.grant(creator.principal(), permission); }
+
+        this.grant(String::from(ANYONE), Permission::Subscribe);
+        this.grant(String::from(ANYONE), Permission::ReadMetadata);
+
+        this
+    }
+
+    /// Reply queue: its creator may do everything, anyone may publish replies to it.
+    pub fn for_reply_queue(creator: &Client) -> Acl
+    {
+        let mut this = Acl::new();
+
+        for permission in Permission::all() { this.grant(creator.principal(), permission); }
+
+        this.grant(String::from(ANYONE), Permission::Publish);
+
+        this
+    }
+
+    /// Broker-wide grants from the `[auth]` settings: operators may manage roles and create queues.
+    pub fn global() -> Acl
+    {
+        let mut this = Acl::new();
+        let auth     = config::settings().auth;
+
+        if auth.anonymous_create { this.grant(String::from(ANYONE), Permission::Create); }
+
+        for operator in auth.operators
+        {
+            this.grant(operator.clone(), Permission::Create);
+            this.grant(operator,         Permission::Manage);
+        }
+
+        this
+    }
+
+    pub fn grant(&mut self, principal: String, permission: Permission)
+    {
+        let permissions = self.grants.entry(principal).or_default();
+
+        if !permissions.contains(&permission) { permissions.push(permission); }
+    }
+
+    pub fn revoke(&mut self, principal: &str, permission: Permission) -> bool
+    {
+        match self.grants.get_mut(principal)
+        {
+            Some(permissions) =>
+            {
+                let before = permissions.len();
+
+                permissions.retain(|p| *p != permission);
+                before != permissions.len()
+            },
+            None => false,
+        }
+    }
+
+    pub fn allows(&self, client: &Client, permission: Permission) -> bool
+    {
+        principals(client).iter().any(|principal| match self.grants.get(principal)
+        {
+            Some(permissions) => permissions.contains(&permission),
+            None              => false,
+        })
+    }
+
+    pub fn check(&self, client: &Client, permission: Permission, target: &str) -> Result<(), QueueError>
+    {
+        if self.allows(client, permission)
+        {
+            Ok(())
+        } else {
+            Err(QueueError::Forbidden(format!("\"{}\" has no \"{}\" permission on \"{}\"", client, permission, target)))
+        }
+    }
+}
+
+impl Acl
+{
+    /// Inverse of the `JsonValue` conversion; unknown permissions are skipped.
+    pub fn from_json(value: &json::JsonValue) -> Acl
+    {
+        let mut this = Acl::new();
+
+        for (principal, permissions) in value.entries()
+        {
+            for permission in permissions.members().filter_map(|p| p.as_str()).filter_map(|p| p.parse::<Permission>().ok())
+            {
+                this.grant(principal.to_string(), permission);
+            }
+        }
+
+        this
+    }
+}
+
+impl std::convert::From<Acl> for json::JsonValue
+{
+    fn from(acl: Acl) -> Self
+    {
+        model::to_json(&acl)
+    }
+}
+
+lazy_static!
+{
+    /// Roles assigned to a principal (see `principals`).
+    pub static ref ROLES:  Mutex<HashMap<String, Vec<String>>> = Mutex::new(HashMap::new());
+    /// Broker-wide grants, checked for `create` and for role management.
+    pub static ref GLOBAL: Mutex<Acl>                          = Mutex::new(Acl::global());
+}
+
+/// Every grant key that applies to `client`, most specific first: its certificate identity or
+/// `host:port`, its host under `auth.host_principals`, their roles, then `*`.
+pub fn principals(client: &Client) -> Vec<String>
+{
+    _principals(client, config::current().auth.host_principals)
+}
+
+fn _principals(client: &Client, host_principals: bool) -> Vec<String>
+{
+    let mut keys = vec![client.to_string()];
+    let roles    = ROLES.lock().unwrap();
+
+    if client._principal(host_principals) != keys[0] { keys.push(client._principal(host_principals)); }
+
+    for key in keys.clone()
+    {
+        if let Some(names) = roles.get(&key)
+        {
+            for name in names { keys.push(format!("{}{}", ROLE_PREFIX, name)); }
+        }
+    }
+
+    keys.push(String::from(ANYONE));
+
+    keys
+}
+
+pub fn assign_role(principal: String, role: String) -> Vec<String>
+{
+    let mut roles = ROLES.lock().unwrap();
+    let names     = roles.entry(principal).or_default();
+
+    if !names.contains(&role) { names.push(role); }
+
+    names.clone()
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn _client(host: &str, port: u64) -> Client
+    {
+        Client::new(String::from(host), port)
+    }
+
+    #[test]
+    fn queue_creators_may_do_everything_and_others_only_read()
+    {
+        let creator = _client("127.0.0.1", 1);
+        let acl     = Acl::for_queue(&creator);
+        let other   = _client("10.0.0.9", 1);
+
+        assert!(Permission::all().into_iter().all(|permission| acl.allows(&creator, permission)));
+        assert!(acl.allows(&other, Permission::Subscribe) && acl.allows(&other, Permission::ReadMetadata));
+        assert!(!acl.allows(&other, Permission::Publish) && !acl.allows(&other, Permission::Manage));
+        assert!(matches!(acl.check(&other, Permission::Manage, "jobs"), Err(QueueError::Forbidden(_))));
+    }
+
+    #[test]
+    fn reply_queues_take_replies_from_anyone()
+    {
+        let creator = _client("127.0.0.1", 1);
+        let acl     = Acl::for_reply_queue(&creator);
+        let other   = _client("10.0.0.9", 1);
+
+        assert!(acl.allows(&other, Permission::Publish));
+        assert!(!acl.allows(&other, Permission::Subscribe));
+    }
+
+    #[test]
+    fn clients_of_one_host_do_not_share_grants()
+    {
+        let creator = _client("127.0.0.1", 1);
+        let acl     = Acl::for_queue(&creator);
+        let local   = _client("127.0.0.1", 2);
+
+        assert_eq!(creator.principal(), "127.0.0.1:1");
+        assert!(!acl.allows(&local, Permission::Manage));
+
+        let mut host = Acl::new();
+
+        host.grant(String::from("127.0.0.1"), Permission::Manage);
+
+        assert!(!host.allows(&local, Permission::Manage));
+    }
+
+    #[test]
+    fn host_principals_share_grants_when_asked()
+    {
+        let local = _client("127.0.0.1", 2);
+
+        assert_eq!(_principals(&local, true), vec![String::from("127.0.0.1:2"), String::from("127.0.0.1"), String::from(ANYONE)]);
+        assert_eq!(_principals(&local, false), vec![String::from("127.0.0.1:2"), String::from(ANYONE)]);
+        assert_eq!(local._principal(true), "127.0.0.1");
+    }
+
+    #[test]
+    fn identities_are_their_own_principal()
+    {
+        let client  = Client { identity: Some(String::from("billing")), ..Client::new(String::from("127.0.0.1"), 1) };
+        let mut acl = Acl::new();
+
+        acl.grant(String::from("127.0.0.1:1"), Permission::Publish);
+        acl.grant(String::from("billing"), Permission::Subscribe);
+
+        assert_eq!(_principals(&client, true), vec![String::from("billing"), String::from(ANYONE)]);
+        assert!(acl.allows(&client, Permission::Subscribe));
+        assert!(!acl.allows(&client, Permission::Publish));
+    }
+
+    #[test]
+    fn roles_carry_their_grants()
+    {
+        let client  = _client("10.0.0.7", 7);
+        let mut acl = Acl::new();
+
+        acl.grant(format!("{}auditors", ROLE_PREFIX), Permission::ReadMetadata);
+
+        assert!(!acl.allows(&client, Permission::ReadMetadata));
+        assert_eq!(assign_role(String::from("10.0.0.7:7"), String::from("auditors")), vec![String::from("auditors")]);
+        assert!(acl.allows(&client, Permission::ReadMetadata));
+    }
+
+    #[test]
+    fn revoking_takes_back_one_permission()
+    {
+        let mut acl = Acl::new();
+        let client  = _client("10.0.0.8", 8);
+
+        acl.grant(client.to_string(), Permission::Publish);
+        acl.grant(client.to_string(), Permission::Publish);
+        acl.grant(client.to_string(), Permission::Subscribe);
+
+        assert!(acl.revoke(&client.to_string(), Permission::Publish));
+        assert!(!acl.revoke(&client.to_string(), Permission::Publish));
+        assert!(!acl.revoke("nobody", Permission::Publish));
+        assert!(acl.allows(&client, Permission::Subscribe) && !acl.allows(&client, Permission::Publish));
+    }
+
+    #[test]
+    fn acls_round_trip_through_json()
+    {
+        let acl      = Acl::for_queue(&_client("127.0.0.1", 1));
+        let restored = Acl::from_json(&json::JsonValue::from(acl.clone()));
+
+        for permission in Permission::all()
+        {
+            assert_eq!(restored.allows(&_client("127.0.0.1", 1), permission), acl.allows(&_client("127.0.0.1", 1), permission));
+        }
+
+        assert_eq!("read_metadata".parse::<Permission>(), Ok(Permission::ReadMetadata));
+        assert!(matches!("admin".parse::<Permission>(), Err(QueueError::Invalid(_))));
+    }
+
+    #[test]
+    fn operators_are_only_those_configured()
+    {
+        let global = Acl::global();
+
+        assert!(!global.allows(&_client("127.0.0.1", 1), Permission::Manage));
+        assert!(global.allows(&_client("127.0.0.1", 1), Permission::Create));
+    }
+}
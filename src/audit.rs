@@ -0,0 +1,290 @@
+use super::*;
+use std::collections::VecDeque;
+use std::time::UNIX_EPOCH;
+use chrono::SecondsFormat;
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT:     usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action
+{
+    Created,
+    Published,
+    Consumed,
+    Acked,
+    Subscribed,
+    Unsubscribed,
+    Deleted,
+}
+
+impl std::str::FromStr for Action
+{
+    type Err = QueueError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name
+        {
+            "created"      => Ok(Action::Created),
+            "published"    => Ok(Action::Published),
+            "consumed"     => Ok(Action::Consumed),
+            "acked"        => Ok(Action::Acked),
+            "subscribed"   => Ok(Action::Subscribed),
+            "unsubscribed" => Ok(Action::Unsubscribed),
+            "deleted"      => Ok(Action::Deleted),
+            _              => Err(QueueError::Invalid(format!("\"{}\" is not an audit action", name))),
+        }
+    }
+}
+
+impl fmt::Display for Action
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Action::Created      => "created",
+            Action::Published    => "published",
+            Action::Consumed     => "consumed",
+            Action::Acked        => "acked",
+            Action::Subscribed   => "subscribed",
+            Action::Unsubscribed => "unsubscribed",
+            Action::Deleted      => "deleted",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry
+{
+    pub id:     u64,
+    pub at:     SystemTime,
+    pub action: Action,
+    pub queue:  String,
+}
+
+impl std::convert::From<Entry> for json::JsonValue
+{
+    fn from(entry: Entry) -> Self
+    {
+        let at: DateTime<Utc> = entry.at.into();
+
+        json::object!
+        {
+            "id"     => entry.id,
+            "at"     => at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "action" => entry.action.to_string(),
+            "queue"  => entry.queue,
+        }
+    }
+}
+
+/// Filters for `query`; `before` is the cursor returned as `next` by the previous page.
+#[derive(Debug, Clone, Default)]
+pub struct Query
+{
+    pub since:  Option<SystemTime>,
+    pub until:  Option<SystemTime>,
+    pub before: Option<u64>,
+    pub action: Option<Action>,
+    pub queue:  Option<String>,
+    pub limit:  usize,
+}
+
+/// Unix seconds or RFC 3339; also used by the `listing` filters.
+pub fn parse_time(name: &str, value: &str) -> Result<SystemTime, QueueError>
+{
+    if let Ok(seconds) = value.parse::<f64>()
+    {
+        if seconds >= 0.0
+        {
+            return Duration::try_from_secs_f64(seconds).ok().and_then(|offset| UNIX_EPOCH.checked_add(offset))
+                .ok_or_else(|| QueueError::Invalid(format!("\"{}\" is out of range, got \"{}\"", name, value)));
+        }
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| SystemTime::from(time.with_timezone(&Utc)))
+        .map_err(|_| QueueError::Invalid(format!("\"{}\" must be unix seconds or RFC 3339, got \"{}\"", name, value)))
+}
+
+/// `limit` query parameter, 1 to `MAX_LIMIT`, `DEFAULT_LIMIT` when absent.
+pub fn parse_limit(value: Option<String>) -> Result<usize, QueueError>
+{
+    match value
+    {
+        Some(value) => value.parse::<usize>().ok().filter(|l| *l > 0 && *l <= MAX_LIMIT)
+            .ok_or_else(|| QueueError::Invalid(format!("\"limit\" must be 1-{}, got \"{}\"", MAX_LIMIT, value))),
+        None        => Ok(DEFAULT_LIMIT),
+    }
+}
+
+impl Query
+{
+    /// Query string parameters `parse` reads, besides the `principal` of `/user_log`.
+    pub const PARAMS: &'static [&'static str] = &["principal", "since", "until", "cursor", "action", "queue", "limit"];
+
+    pub fn parse<F: Fn(&str) -> Option<String>>(param: &F) -> Result<Query, QueueError>
+    {
+        Ok(Query
+        {
+            since:  match param("since")  { Some(v) => Some(parse_time("since", &v)?), None => None },
+            until:  match param("until")  { Some(v) => Some(parse_time("until", &v)?), None => None },
+            before: match param("cursor") { Some(v) => Some(v.parse::<u64>().map_err(|_| QueueError::Invalid(format!("\"cursor\" \"{}\" is not valid", v)))?), None => None },
+            action: match param("action") { Some(v) => Some(v.parse::<Action>()?), None => None },
+            queue:  param("queue"),
+            limit:  parse_limit(param("limit"))?,
+        })
+    }
+
+    fn matches(&self, entry: &Entry) -> bool
+    {
+        self.before.is_none_or(|id|   entry.id < id)
+        && self.since.is_none_or(|t|  entry.at >= t)
+        && self.until.is_none_or(|t|  entry.at <= t)
+        && self.action.is_none_or(|a| entry.action == a)
+        && self.queue.as_ref().is_none_or(|q| entry.queue == *q)
+    }
+}
+
+struct Trail
+{
+    next_id: u64,
+    entries: HashMap<String, VecDeque<Entry>>,
+}
+
+impl Trail
+{
+    /// Appends to `principal`'s entries, keeping at most `per_principal` of them and at most
+    /// `max_principals` trails; a new principal displaces the one whose last entry is oldest.
+    fn push(&mut self, principal: String, action: Action, queue: &str, per_principal: usize, max_principals: usize)
+    {
+        if !self.entries.contains_key(&principal) && self.entries.len() >= max_principals
+        {
+            let idle = self.entries.iter()
+                .min_by_key(|(_, entries)| entries.back().map(|entry| entry.id))
+                .map(|(name, _)| name.clone());
+
+            if let Some(idle) = idle { self.entries.remove(&idle); }
+        }
+
+        let id      = self.next_id;
+        let entries = self.entries.entry(principal).or_default();
+
+        entries.push_back(Entry { id, at: SystemTime::now(), action, queue: String::from(queue) });
+
+        while entries.len() > per_principal { entries.pop_front(); }
+
+        self.next_id += 1;
+    }
+}
+
+lazy_static!
+{
+    static ref TRAIL: Mutex<Trail> = Mutex::new(Trail { next_id: 1, entries: HashMap::new() });
+}
+
+pub fn record(client: &Client, action: Action, queue: &str)
+{
+    let settings  = config::current();
+    let principal = client._principal(settings.auth.host_principals);
+
+    TRAIL.lock().unwrap().push(principal, action, queue, settings.audit.per_principal, settings.audit.max_principals);
+}
+
+/// One page of `principal`'s entries, newest first, with the cursor of the next page.
+pub fn query(principal: &str, filter: &Query) -> json::JsonValue
+{
+    let trail = TRAIL.lock().unwrap();
+    let page  = match trail.entries.get(principal)
+    {
+        Some(entries) => entries.iter().rev().filter(|entry| filter.matches(entry)).take(filter.limit + 1).cloned().collect::<Vec<Entry>>(),
+        None          => Vec::new(),
+    };
+
+    let next = if page.len() > filter.limit { Some(page[filter.limit - 1].id) } else { None };
+
+    json::object!
+    {
+        "principal" => principal,
+        "entries"   => page.into_iter().take(filter.limit).collect::<Vec<Entry>>(),
+        "next"      => next,
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn _trail() -> Trail
+    {
+        Trail { next_id: 1, entries: HashMap::new() }
+    }
+
+    fn _ids(trail: &Trail, principal: &str) -> Vec<u64>
+    {
+        trail.entries.get(principal).map(|entries| entries.iter().map(|entry| entry.id).collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_first()
+    {
+        let mut trail = _trail();
+
+        for _ in 0..5 { trail.push(String::from("a"), Action::Published, "q", 3, 10); }
+
+        assert_eq!(_ids(&trail, "a"), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn idlest_principal_makes_room()
+    {
+        let mut trail = _trail();
+
+        trail.push(String::from("a"), Action::Created,   "q", 10, 2);
+        trail.push(String::from("b"), Action::Published, "q", 10, 2);
+        trail.push(String::from("a"), Action::Consumed,  "q", 10, 2);
+        trail.push(String::from("c"), Action::Acked,     "q", 10, 2);
+
+        assert_eq!(trail.entries.len(), 2);
+        assert_eq!(_ids(&trail, "a"), vec![1, 3]);
+        assert_eq!(_ids(&trail, "b"), Vec::<u64>::new());
+        assert_eq!(_ids(&trail, "c"), vec![4]);
+    }
+
+    #[test]
+    fn pages_run_newest_first()
+    {
+        let client = Client::new(String::from("10.0.33.1"), 4001);
+
+        for queue in ["a", "b", "c"] { record(&client, Action::Published, queue); }
+
+        let filter = Query { limit: 2, ..Query::default() };
+        let first  = query(&client.principal(), &filter);
+        let queues = |page: &json::JsonValue| page["entries"].members().map(|entry| entry["queue"].to_string()).collect::<Vec<String>>();
+
+        assert_eq!(queues(&first), vec!["c", "b"]);
+
+        let next   = first["next"].as_u64().unwrap();
+        let second = query(&client.principal(), &Query { before: Some(next), ..filter.clone() });
+
+        assert_eq!(queues(&second), vec!["a"]);
+        assert!(second["next"].is_null());
+    }
+
+    #[test]
+    fn times_are_checked()
+    {
+        assert_eq!(parse_time("since", "0"), Ok(UNIX_EPOCH));
+        assert_eq!(parse_time("since", "1970-01-01T00:00:10Z"), Ok(UNIX_EPOCH + Duration::from_secs(10)));
+        assert!(matches!(parse_time("since", "1e300"),     Err(QueueError::Invalid(_))));
+        assert!(matches!(parse_time("since", "yesterday"), Err(QueueError::Invalid(_))));
+        assert!(matches!(parse_limit(Some(String::from("0"))), Err(QueueError::Invalid(_))));
+        assert_eq!(parse_limit(None), Ok(DEFAULT_LIMIT));
+    }
+}
@@ -0,0 +1,316 @@
+//! Payloads too large to keep in memory, spilled to files under `messages.spill_dir`.
+//!
+//! A payload longer than `messages.spill_above` bytes is written to its own file as published and
+//! only a `Blob` naming it stays in the queue. Copies of a message share the `Blob`; the file is
+//! removed when the last of them is dropped, i.e. once the message leaves its queue: taken by
+//! every recipient or expired (both dropped by the next `Queue::expire`), purged, taken as a
+//! reply, or deleted along with the queue. Copies only outlive that while an answer holding them
+//! is written.
+//! Files are never read back from a previous run: snapshots inline every payload. A directory the
+//! settings name is cleared by `sweep` at startup; otherwise each process spills to a directory of
+//! its own, which no other run can have left files in.
+
+use super::*;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Suffix of spilled payload files; `sweep` removes nothing else.
+const EXTENSION: &str = "blob";
+
+#[derive(Debug)]
+pub struct Blob
+{
+    path: PathBuf,
+    len:  usize,
+}
+
+impl Blob
+{
+    /// Writes `text` to a new file in `dir`, creating `dir` if needed.
+    pub fn write(dir: &Path, text: &str) -> io::Result<Blob>
+    {
+        fs::create_dir_all(dir)?;
+
+        let path = dir.join(format!("{:016x}.{}", rand::random::<u64>(), EXTENSION));
+
+        fs::write(&path, text)?;
+
+        Ok(Blob { path, len: text.len() })
+    }
+
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    /// Size of the payload in bytes.
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    pub fn read(&self) -> io::Result<String>
+    {
+        fs::read_to_string(&self.path)
+    }
+
+    /// The first `bytes` bytes, or fewer at the end of the file; a character cut in two at the end
+    /// comes out as `U+FFFD`.
+    pub fn prefix(&self, bytes: usize) -> io::Result<String>
+    {
+        let mut head = Vec::new();
+
+        io::Read::read_to_end(&mut io::Read::take(self.open()?, bytes as u64), &mut head)?;
+
+        Ok(String::from_utf8_lossy(&head).into_owned())
+    }
+
+    /// The file, for streaming the payload without reading it into memory.
+    pub fn open(&self) -> io::Result<fs::File>
+    {
+        fs::File::open(&self.path)
+    }
+}
+
+impl Drop for Blob
+{
+    fn drop(&mut self)
+    {
+        if let Err(err) = fs::remove_file(&self.path)
+        {
+            logging::event(logging::Level::Warn, "blob not removed", json::object!{"path" => self.path.display().to_string(), "error" => err.to_string()});
+        }
+    }
+}
+
+/// `messages.spill_dir`; `blobs` inside `data_dir`, or `miniq-blobs-<pid>` in the system temp dir, if unset.
+pub fn dir(settings: &config::Settings) -> PathBuf
+{
+    match (&settings.messages.spill_dir, &settings.data_dir)
+    {
+        (Some(dir), _)    => PathBuf::from(dir),
+        (None, Some(dir)) => Path::new(dir).join("blobs"),
+        (None, None)      => std::env::temp_dir().join(format!("miniq-blobs-{}", std::process::id())),
+    }
+}
+
+/// Whether `dir` is named by the settings, and so shared with other runs, rather than this process's own.
+pub fn configured(settings: &config::Settings) -> bool
+{
+    settings.messages.spill_dir.is_some() || settings.data_dir.is_some()
+}
+
+/// Removes payload files left in `dir` by a previous run; returns how many.
+pub fn sweep(dir: &Path) -> Result<usize, String>
+{
+    let entries = match fs::read_dir(dir)
+    {
+        Ok(entries)                                       => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err)                                          => return Err(format!("\"{}\": {}", dir.display(), err)),
+    };
+
+    let mut removed = 0;
+
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+    {
+        fs::remove_file(&path).map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// `sweep`s `dir`, then removes it if it is left empty; returns how many files were removed.
+pub fn remove(dir: &Path) -> Result<usize, String>
+{
+    let removed = sweep(dir)?;
+
+    match fs::remove_dir(dir)
+    {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(format!("\"{}\": {}", dir.display(), err)),
+        _                                                 => Ok(removed),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// A fresh directory for test `name`.
+    fn _dir(name: &str) -> PathBuf
+    {
+        let dir = std::env::temp_dir().join(format!("miniq-test-{}-{}", std::process::id(), name));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn _files(dir: &Path) -> usize
+    {
+        fs::read_dir(dir).map(|entries| entries.count()).unwrap_or(0)
+    }
+
+    /// Settings spilling every payload over 4 bytes to `dir`.
+    fn _settings(dir: &Path) -> config::Settings
+    {
+        let messages = config::MessageSettings { spill_above: Some(4), spill_dir: Some(dir.display().to_string()), ..config::MessageSettings::default() };
+
+        config::Settings { messages, ..config::Settings::default() }
+    }
+
+    /// A queue holding `data`, spilled to `dir`, for subscriber `127.0.0.1:2`.
+    fn _queue(dir: &Path, data: &str) -> (Queue, Client)
+    {
+        let publisher  = Client::new(String::from("127.0.0.1"), 1);
+        let subscriber = Client::new(String::from("127.0.0.1"), 2);
+        let mut queue  = Queue::new(String::from("jobs"), publisher.clone());
+
+        queue.sub(subscriber.clone()).unwrap();
+        queue.push(Payload::pack(String::from(data), &_settings(dir), false), publisher, None, None, Properties::default()).unwrap();
+
+        (queue, subscriber)
+    }
+
+    #[test]
+    fn previews_read_only_a_prefix()
+    {
+        let dir     = _dir("preview");
+        let payload = Payload::pack(String::from("héllo wörld"), &_settings(&dir), false);
+
+        assert!(matches!(payload, Payload::Spilled(_)));
+        assert_eq!(payload.preview(5).unwrap(), "héllo");
+        assert_eq!(payload.preview(50).unwrap(), "héllo wörld");
+
+        if let Payload::Spilled(blob) = &payload { assert_eq!(blob.prefix(2).unwrap(), "h\u{FFFD}"); }
+
+        drop(payload);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_live_as_long_as_their_last_copy()
+    {
+        let dir  = _dir("copies");
+        let blob = Arc::new(Blob::write(&dir, "payload").unwrap());
+        let copy = blob.clone();
+        let path = blob.path().to_path_buf();
+
+        assert_eq!((blob.read().unwrap(), blob.len()), (String::from("payload"), 7));
+
+        drop(blob);
+
+        assert!(path.exists());
+
+        drop(copy);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn consumed_messages_remove_their_file()
+    {
+        let dir                 = _dir("consumed");
+        let (mut queue, reader) = _queue(&dir, "payload");
+
+        assert!(matches!(queue.data[0].data, Payload::Spilled(_)));
+        assert_eq!(_files(&dir), 1);
+
+        let message = queue.consume(&reader, SystemTime::now()).unwrap().unwrap();
+
+        assert_eq!(message.data.to_string(), "payload");
+
+        drop(message);
+        queue.expire(SystemTime::now());
+
+        assert_eq!(_files(&dir), 0);
+    }
+
+    #[test]
+    fn unreadable_payloads_fail_and_stay_queued()
+    {
+        let dir                 = _dir("unreadable");
+        let (mut queue, reader) = _queue(&dir, "payload");
+
+        if let Payload::Spilled(blob) = &queue.data[0].data { fs::remove_file(blob.path()).unwrap(); }
+
+        assert!(matches!(queue.consume(&reader, SystemTime::now()), Err(QueueError::Internal(_))));
+        assert!(matches!(queue.take(&reader, 1, SystemTime::now()), Err(QueueError::Internal(_))));
+        assert!(queue.data[0].active && queue.data[0].recipients == vec![reader]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn expired_and_purged_messages_remove_their_file()
+    {
+        let dir            = _dir("expired");
+        let (mut queue, _) = _queue(&dir, "payload");
+
+        queue.expire(SystemTime::now() + Duration::from_secs(3600));
+
+        assert_eq!(_files(&dir), 0);
+
+        let (mut queue, _) = _queue(&dir, "payload");
+        let creator        = Client::new(String::from("127.0.0.1"), 1);
+
+        queue.purge(&creator).unwrap();
+
+        assert_eq!(_files(&dir), 0);
+    }
+
+    #[test]
+    fn short_payloads_stay_in_memory()
+    {
+        let dir        = _dir("short");
+        let (queue, _) = _queue(&dir, "tiny");
+
+        assert!(matches!(queue.data[0].data, Payload::Plain(_)));
+        assert_eq!(_files(&dir), 0);
+    }
+
+    #[test]
+    fn sweep_and_remove_take_blobs_only()
+    {
+        let dir = _dir("sweep");
+
+        assert_eq!(sweep(&dir), Ok(0));
+
+        std::mem::forget(Blob::write(&dir, "left over").unwrap());
+        fs::write(dir.join("notes.txt"), "kept").unwrap();
+
+        assert_eq!(sweep(&dir), Ok(1));
+        assert_eq!(_files(&dir), 1);
+        assert!(remove(&dir).is_err());
+
+        fs::remove_file(dir.join("notes.txt")).unwrap();
+        std::mem::forget(Blob::write(&dir, "left over").unwrap());
+
+        assert_eq!(remove(&dir), Ok(1));
+        assert!(!dir.exists());
+        assert_eq!(remove(&dir), Ok(0));
+    }
+
+    #[test]
+    fn only_named_directories_are_shared()
+    {
+        let settings = config::Settings::default();
+
+        assert!(!configured(&settings));
+        assert!(dir(&settings).ends_with(format!("miniq-blobs-{}", std::process::id())));
+
+        let settings = config::Settings { data_dir: Some(String::from("/var/lib/miniq")), ..config::Settings::default() };
+
+        assert!(configured(&settings));
+        assert_eq!(dir(&settings), PathBuf::from("/var/lib/miniq/blobs"));
+    }
+}
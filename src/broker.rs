@@ -0,0 +1,336 @@
+use super::*;
+
+use std::sync::MutexGuard;
+
+pub type Queues = HashMap<String, Arc<Mutex<Queue>>>;
+
+/// Names of generated reply queues start with this.
+pub const REPLY_PREFIX: &str = "reply.";
+/// How often `request` looks for its reply.
+const REPLY_POLL: Duration = Duration::from_millis(10);
+
+/// A set of queues with create/publish/subscribe/consume, usable in-process without the HTTP server.
+///
+/// Clones share the same queues. Every operation applies the queue and broker-wide ACLs exactly like
+/// the HTTP routes; each broker counts its own `memory`, while settings, roles, the audit trail,
+/// metrics and logging stay process-wide.
+#[derive(Clone, Default)]
+pub struct Broker
+{
+    queues:                  Arc<Mutex<Queues>>,
+    pub(crate) transactions: Arc<Mutex<HashMap<String, transaction::Transaction>>>,
+    pub(crate) memory:       Arc<memory::Memory>,
+}
+
+impl Broker
+{
+    pub fn new() -> Broker
+    {
+        Broker::default()
+    }
+
+    /// Locks the queue map, recording how long the lock took in `metrics`.
+    pub fn queues(&self) -> MutexGuard<'_, Queues>
+    {
+        let started = Instant::now();
+        let guard   = self.queues.lock().unwrap();
+
+        metrics::observe_lock_wait(started.elapsed());
+
+        guard
+    }
+
+    pub fn names(&self) -> Vec<String>
+    {
+        let mut names = self.queues().keys().cloned().collect::<Vec<String>>();
+
+        names.sort();
+
+        names
+    }
+
+    /// Runs `f` on the named queue in place, under its lock.
+    pub fn update<T, F: FnOnce(&mut Queue) -> Result<T, QueueError>>(&self, name: &str, f: F) -> Result<T, QueueError>
+    {
+        let q = self.queues().get(name).cloned().ok_or_else(|| QueueError::NotFound(format!("\"{}\" not exists", name)))?;
+        let mut queue = q.lock().unwrap();
+
+        f(&mut queue)
+    }
+
+    /// Stores `data` as the settings and this broker's memory say; done before any queue is
+    /// locked, since it may compress the payload or write it to a file.
+    pub(crate) fn pack(&self, data: String) -> Payload
+    {
+        let settings = config::settings();
+        let paging   = self.memory.pages(&settings);
+
+        Payload::pack(data, &settings, paging)
+    }
+
+    /// Inserts `queue` as it is, replacing any queue of the same name.
+    pub fn insert(&self, queue: Queue)
+    {
+        self.queues().insert(queue.name.clone(), Arc::new(Mutex::new(queue)));
+    }
+
+    /// Creates `name` owned by `client` if the broker-wide ACL allows it.
+    pub fn create(&self, client: &Client, name: &str) -> Result<(), QueueError>
+    {
+        acl::GLOBAL.lock().unwrap().check(client, acl::Permission::Create, name)?;
+
+        let mut qs = self.queues();
+
+        if qs.contains_key(name) { return Err(QueueError::AlreadyExists(format!("\"{}\" allready exists", name))); }
+
+        logging::event(logging::Level::Info, "queue created", json::object!{"queue" => name, "client" => client.to_string()});
+        audit::record(client, audit::Action::Created, name);
+        qs.insert(String::from(name), Arc::new(Mutex::new(Queue::new(String::from(name), client.clone()))));
+
+        Ok(())
+    }
+
+    /// Removes a queue and everything in it; requires `manage` on the queue. Returns how many messages went with it.
+    pub fn delete(&self, client: &Client, name: &str) -> Result<usize, QueueError>
+    {
+        let mut qs   = self.queues();
+        let messages = match qs.get(name)
+        {
+            Some(q) =>
+            {
+                let queue = q.lock().unwrap();
+
+                queue.acl.check(client, acl::Permission::Manage, name)?;
+                queue.data.len()
+            },
+            None    => return Err(QueueError::NotFound(format!("\"{}\" not exists", name))),
+        };
+
+        qs.remove(name);
+        metrics::forget(name);
+        logging::event(logging::Level::Info, "queue deleted", json::object!{"queue" => name, "client" => client.to_string(), "messages" => messages});
+        audit::record(client, audit::Action::Deleted, name);
+
+        Ok(messages)
+    }
+
+    /// `lifetime` is in seconds; the configured default applies when `None`.
+    pub fn publish(&self, client: &Client, name: &str, data: String, lifetime: Option<f64>, priority: Option<usize>) -> Result<MSG, QueueError>
+    {
+        self.publish_with(client, name, data, lifetime, priority, Properties::default())
+    }
+
+    /// `publish` with `reply_to`, `correlation_id` and the other message properties set.
+    pub fn publish_with(&self, client: &Client, name: &str, data: String, lifetime: Option<f64>, priority: Option<usize>, properties: Properties) -> Result<MSG, QueueError>
+    {
+        let reserved = self.memory.admit(data.len())?;
+        let data     = self.pack(data);
+        let result   = self.update(name, |queue| queue.append(data, client.clone(), lifetime, priority, properties).and_then(|(msg, fresh)|
+        {
+            if fresh
+            {
+                queue.published(&msg);
+                self.memory.charge(msg.footprint());
+            }
+
+            msg.loaded()
+        }));
+
+        self.memory.release(reserved);
+
+        result
+    }
+
+    /// Creates an exclusive reply queue for `client` and returns its generated name. It is deleted
+    /// once `client` has not read it for `idle`, or `queue.reply_timeout` seconds when `None`.
+    pub fn create_reply_queue(&self, client: &Client, idle: Option<f64>) -> Result<String, QueueError>
+    {
+        let idle = Duration::from_secs_f64(idle.unwrap_or(config::settings().queue.reply_timeout));
+        let name = format!("{}{:016x}", REPLY_PREFIX, rand::random::<u64>());
+
+        logging::event(logging::Level::Info, "reply queue created", json::object!{"queue" => name.clone(), "client" => client.to_string()});
+        audit::record(client, audit::Action::Created, &name);
+        self.insert(Queue::reply(name.clone(), client.clone(), idle));
+
+        Ok(name)
+    }
+
+    /// Publishes `data` to `name` with a fresh reply queue as `reply_to` and a generated
+    /// `correlation_id`, then waits up to `timeout` seconds, at most `queue.reply_timeout`, for the
+    /// message on the reply queue carrying the same `correlation_id`. The reply queue is deleted
+    /// either way, and `memory` freed of the reply and of anything else it still held.
+    pub fn request(&self, client: &Client, name: &str, data: String, lifetime: Option<f64>, priority: Option<usize>, timeout: Option<f64>) -> Result<MSG, QueueError>
+    {
+        let longest     = config::settings().queue.reply_timeout;
+        let timeout     = timeout.map_or(longest, |timeout| timeout.min(longest));
+        let reply_to    = self.create_reply_queue(client, Some(timeout))?;
+        let correlation = format!("{:016x}", rand::random::<u64>());
+        let properties  = Properties { reply_to: Some(reply_to.clone()), correlation_id: Some(correlation.clone()), ..Properties::default() };
+        let deadline    = Instant::now() + Duration::from_secs_f64(timeout);
+        let result      = self.publish_with(client, name, data, lifetime, priority, properties).and_then(|_| loop
+        {
+            let reply = self.update(&reply_to, |queue| {
+                queue.touch(client);
+
+                let index = queue.data.iter().position(|message| message.active && message.properties.correlation_id.as_deref() == Some(&*correlation));
+
+                Ok(index.map(|index| queue.data.remove(index)))
+            })?;
+
+            match reply
+            {
+                Some(message)                        => { self.memory.free(message.footprint()); break message.loaded() },
+                None if Instant::now() >= deadline   => break Err(QueueError::Timeout(format!("no reply on \"{}\" within {}s", name, timeout))),
+                None                                 => thread::sleep(REPLY_POLL),
+            }
+        });
+
+        if let Some(q) = self.queues().remove(&reply_to) { self.memory.free(q.lock().unwrap().bytes()); }
+
+        metrics::forget(&reply_to);
+
+        result
+    }
+
+    /// Opens a transaction for `client` and returns its generated name.
+    pub fn begin(&self, client: &Client) -> String
+    {
+        let id = format!("{}{:016x}", transaction::PREFIX, rand::random::<u64>());
+
+        self.transactions.lock().unwrap().insert(id.clone(), transaction::Transaction::new(client));
+        logging::event(logging::Level::Debug, "transaction begun", json::object!{"transaction" => id.clone(), "client" => client.to_string()});
+
+        id
+    }
+
+    /// Runs `f` on transaction `id`, which must belong to `client`'s principal; with `close` the
+    /// transaction is removed afterwards.
+    fn _transaction<T, F: FnOnce(&mut transaction::Transaction) -> T>(&self, client: &Client, id: &str, close: bool, f: F) -> Result<T, QueueError>
+    {
+        let mut transactions = self.transactions.lock().unwrap();
+        let transaction      = transactions.get_mut(id).ok_or_else(|| QueueError::NotFound(format!("transaction \"{}\" not exists", id)))?;
+
+        if transaction.owner != client.principal() { return Err(QueueError::Forbidden(format!("transaction \"{}\" belongs to \"{}\"", id, transaction.owner))); }
+
+        transaction.last_seen = Instant::now();
+
+        let result = f(transaction);
+
+        if close { transactions.remove(id); }
+
+        Ok(result)
+    }
+
+    /// Stages `operation` in the transaction `id` of the client staging it; returns how many
+    /// operations the transaction holds. Beyond the transaction's limits, only that the queue
+    /// exists and that flow control lets the payload through is checked until `commit`.
+    pub fn stage(&self, id: &str, operation: transaction::Operation) -> Result<usize, QueueError>
+    {
+        if !self.queues().contains_key(operation.queue()) { return Err(QueueError::NotFound(format!("\"{}\" not exists", operation.queue()))); }
+
+        if let transaction::Operation::Push { message, .. } = &operation { self.memory.release(self.memory.admit(message.data.len())?); }
+
+        let client = operation.client().clone();
+
+        self._transaction(&client, id, false, |transaction| transaction.stage(operation))?
+    }
+
+    /// Applies everything staged in transaction `id` at once, or nothing if one operation fails;
+    /// the transaction is closed either way.
+    pub fn commit(&self, client: &Client, id: &str) -> Result<transaction::Outcome, QueueError>
+    {
+        let staged = self._transaction(client, id, true, |transaction| std::mem::take(&mut transaction.operations))?;
+        let pushed = staged.iter().map(|operation| match operation { transaction::Operation::Push { message, .. } => message.data.len(), _ => 0 }).sum();
+        let result = self.memory.admit(pushed).and_then(|reserved|
+        {
+            let result = transaction::apply(self, &staged);
+
+            self.memory.release(reserved);
+
+            result
+        });
+
+        match &result
+        {
+            Ok(_)    => logging::event(logging::Level::Info, "transaction committed", json::object!{"transaction" => id, "client" => client.to_string(), "operations" => staged.len()}),
+            Err(err) => logging::event(logging::Level::Info, "transaction rolled back", json::object!{"transaction" => id, "client" => client.to_string(), "error" => err.message()}),
+        }
+
+        result
+    }
+
+    /// Discards transaction `id`; returns how many operations it held.
+    pub fn rollback(&self, client: &Client, id: &str) -> Result<usize, QueueError>
+    {
+        let discarded = self._transaction(client, id, true, |transaction| transaction.operations.len())?;
+
+        logging::event(logging::Level::Info, "transaction rolled back", json::object!{"transaction" => id, "client" => client.to_string(), "operations" => discarded});
+
+        Ok(discarded)
+    }
+
+    pub fn subscribe(&self, client: &Client, name: &str) -> Result<Vec<Client>, QueueError>
+    {
+        self.subscribe_with(client, name, None)
+    }
+
+    /// `subscribe` to the messages `selector` matches only.
+    pub fn subscribe_with(&self, client: &Client, name: &str, selector: Option<selector::Selector>) -> Result<Vec<Client>, QueueError>
+    {
+        self.update(name, |queue| queue.sub_with(client.clone(), selector))
+    }
+
+    pub fn unsubscribe(&self, client: &Client, name: &str) -> Result<Vec<Client>, QueueError>
+    {
+        self.update(name, |queue| queue.unsub(client.clone()))
+    }
+
+    /// Takes the next message waiting for subscriber `client`, or `None` if there is nothing for it yet.
+    pub fn consume(&self, client: &Client, name: &str) -> Result<Option<MSG>, QueueError>
+    {
+        self.update(name, |queue| { queue.touch(client); queue.consume(client, SystemTime::now()) })
+    }
+
+    /// Takes message `id` off `name` for subscriber `client`, whatever its priority.
+    pub fn ack(&self, client: &Client, name: &str, id: u64) -> Result<MSG, QueueError>
+    {
+        self.update(name, |queue| { queue.touch(client); queue.ack(client, id) })
+    }
+
+    /// Runs `Queue::expire` over every queue, drops reply queues their owner stopped reading and
+    /// transactions nobody used for `queue.transaction_timeout`, recounts `memory` and pages out
+    /// payloads if it says so; returns how many messages expired.
+    pub fn expire(&self) -> usize
+    {
+        let now     = SystemTime::now();
+        let instant = Instant::now();
+        let mut qs  = self.queues();
+        let expired = qs.values().map(|q| q.lock().unwrap().expire(now)).sum();
+
+        qs.retain(|name, q| {
+            let idle = q.lock().unwrap().is_idle(instant);
+
+            if idle
+            {
+                logging::event(logging::Level::Info, "reply queue dropped", json::object!{"queue" => name.clone()});
+                metrics::forget(name);
+            }
+
+            !idle
+        });
+
+        self.memory.settle(qs.values().map(|q| q.lock().unwrap().bytes()).sum());
+        drop(qs);
+        memory::page(self);
+
+        self.transactions.lock().unwrap().retain(|id, transaction| {
+            let idle = transaction.is_idle(instant);
+
+            if idle { logging::event(logging::Level::Info, "transaction dropped", json::object!{"transaction" => id.clone(), "operations" => transaction.operations.len()}); }
+
+            !idle
+        });
+
+        expired
+    }
+}
@@ -0,0 +1,104 @@
+use super::*;
+
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Client 
+{
+    pub host:     String,
+    pub port:     u64,
+    /// Subject of the client certificate, when connected over mutual TLS.
+    pub identity: Option<String>,
+}
+
+
+impl Client
+{
+    pub fn new(_host: String, _port: u64,) -> Client
+    {
+        Client
+        {
+            host:     _host,
+            port:     _port,
+            identity: None,
+        }
+    }
+
+    pub fn from_request(request: &Request) -> Client
+    {
+        #[allow(unused_mut)]
+        let mut this = Client::new(format!("{}", request.remote_addr.ip()), request.remote_addr.port() as u64);
+
+        #[cfg(feature = "tls")]
+        {
+            this.identity = tls::peer_identity(&request.remote_addr);
+        }
+
+        this
+    }
+
+    /// Name grants, reply queues and transactions belong to: the certificate identity; otherwise
+    /// `host:port`, or the host alone under `auth.host_principals`.
+    pub fn principal(&self) -> String
+    {
+        self._principal(config::current().auth.host_principals)
+    }
+
+    pub(crate) fn _principal(&self, host_principals: bool) -> String
+    {
+        match &self.identity
+        {
+            Some(identity)          => identity.clone(),
+            None if host_principals => self.host.clone(),
+            None                    => self.to_string(),
+        }
+    }
+
+    /// Parses `host:port`; anything else is taken as a certificate identity.
+    pub fn parse(address: &str) -> Option<Client>
+    {
+        let mut parts = address.rsplitn(2, ':');
+
+        match (parts.next(), parts.next())
+        {
+            (Some(port), Some(host)) => port.parse::<u64>().ok().map(|p| Client::new(String::from(host), p)),
+            (Some(name), None)       => Some(Client { host: String::new(), port: 0, identity: Some(String::from(name)) }),
+            _                        => None,
+        }
+    }
+}
+
+
+impl std::convert::From<Client> for json::JsonValue
+{
+    fn from(_client: Client) -> Self
+    {
+        model::to_json(&_client)
+    }
+}
+
+
+impl fmt::Display for Client 
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        match &self.identity
+        {
+            Some(identity) => write!(f, "{}", identity),
+            None           => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+
+impl std::cmp::PartialEq for Client
+{
+    fn eq(&self, other: &Self) -> bool 
+    {
+        match (&self.identity, &other.identity)
+        {
+            (Some(a), Some(b)) => a == b,
+            (None,    None)    => self.host == other.host && self.port == other.port,
+            _                  => false,
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Compression of stored payloads and of HTTP bodies.
+//!
+//! A payload longer than `compression.threshold` bytes is kept compressed with `compression.codec`
+//! when that makes it smaller, and decompressed whenever it is read, so consumers always see the
+//! text that was published. Independently of the codec, request bodies sent with
+//! `Content-Encoding: gzip` or `zstd` are decoded, and responses longer than the threshold are
+//! compressed for clients whose `Accept-Encoding` allows it, preferring zstd.
+
+use super::*;
+
+use std::io;
+use iron::headers::{AcceptEncoding, ContentEncoding, Encoding};
+use flate2::{read::GzDecoder, write::GzEncoder};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec
+{
+    /// Payloads are stored as published.
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Codec
+{
+    /// The `Content-Encoding` naming it.
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            Codec::None => "identity",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    fn from_encoding(encoding: &Encoding) -> Option<Codec>
+    {
+        match encoding
+        {
+            Encoding::Identity                                               => Some(Codec::None),
+            Encoding::Gzip                                                   => Some(Codec::Gzip),
+            Encoding::EncodingExt(name) if name.eq_ignore_ascii_case("zstd") => Some(Codec::Zstd),
+            _                                                                => None,
+        }
+    }
+
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>>
+    {
+        match self
+        {
+            Codec::None => Ok(bytes.to_vec()),
+            Codec::Gzip =>
+            {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+            Codec::Zstd => zstd::bulk::compress(bytes, 0),
+        }
+    }
+
+    fn _decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>>
+    {
+        Ok(match self
+        {
+            Codec::None => Box::new(bytes),
+            Codec::Gzip => Box::new(GzDecoder::new(bytes)),
+            Codec::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(bytes)?),
+        })
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>>
+    {
+        let mut buffer = Vec::new();
+
+        self._decoder(bytes)?.read_to_end(&mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+/// At most `limit` bytes of `reader`; `TooLarge` if it has more.
+fn _read_at_most<R: Read>(reader: R, limit: usize) -> Result<Vec<u8>, QueueError>
+{
+    let mut buffer = Vec::new();
+
+    reader.take(limit as u64 + 1).read_to_end(&mut buffer).map_err(|e| QueueError::BadRequest(format!("{}", e)))?;
+
+    if buffer.len() > limit { return Err(QueueError::TooLarge(format!("body is over {} bytes", limit))); }
+
+    Ok(buffer)
+}
+
+/// The request body, decoded as its `Content-Encoding` says; neither the body nor any stage of
+/// its decoding may be over `limit` bytes.
+pub fn read_body(request: &mut Request, limit: usize) -> Result<Vec<u8>, QueueError>
+{
+    let encodings  = request.headers.get::<ContentEncoding>().map(|header| header.0.clone()).unwrap_or_default();
+    let mut buffer = _read_at_most(request.body.by_ref(), limit)?;
+
+    // Encodings are listed in the order they were applied.
+    for encoding in encodings.iter().rev()
+    {
+        let codec   = Codec::from_encoding(encoding).ok_or_else(|| QueueError::BadRequest(format!("unsupported Content-Encoding \"{}\"", encoding)))?;
+        let decoder = codec._decoder(&buffer).map_err(|e| QueueError::BadRequest(format!("cannot decode {} body: {}", codec.name(), e)))?;
+
+        buffer = _read_at_most(decoder, limit).map_err(|err| match err
+        {
+            QueueError::BadRequest(e) => QueueError::BadRequest(format!("cannot decode {} body: {}", codec.name(), e)),
+            other                     => other,
+        })?;
+    }
+
+    Ok(buffer)
+}
+
+/// The codec `request` accepts most, zstd before gzip on a tie; `None` if it takes neither.
+fn _accepted(request: &Request) -> Option<Codec>
+{
+    let accepted = request.headers.get::<AcceptEncoding>()?;
+
+    accepted.0.iter()
+        .filter_map(|item| Codec::from_encoding(&item.item).filter(|codec| *codec != Codec::None).map(|codec| (item.quality, codec)))
+        .filter(|(quality, _)| quality.0 > 0)
+        .max_by_key(|(quality, codec)| (*quality, *codec == Codec::Zstd))
+        .map(|(_, codec)| codec)
+}
+
+/// Compresses the body of `response` for `request` when it is longer than `compression.threshold`
+/// and the client accepts gzip or zstd; any other response, including one that already names its
+/// `Content-Encoding`, is returned untouched. A body that cannot be read is already partly
+/// consumed, so it is answered with `500 internal` rather than sent truncated.
+pub fn encode_response(request: &Request, response: Response) -> Response
+{
+    _encode(_accepted(request), response, config::settings().compression.threshold)
+}
+
+/// `encode_response` for a client accepting `accepted`.
+fn _encode(accepted: Option<Codec>, mut response: Response, threshold: usize) -> Response
+{
+    let codec = match accepted
+    {
+        Some(codec) if !response.headers.has::<ContentEncoding>() => codec,
+        _                                                         => return response,
+    };
+
+    let mut body = Vec::new();
+
+    match response.body.take()
+    {
+        Some(mut content) => if let Err(err) = content.write_body(&mut body)
+        {
+            logging::event(logging::Level::Error, "response body unreadable", json::object!{"error" => err.to_string()});
+
+            return QueueError::Internal(format!("cannot read the response body: {}", err)).response();
+        },
+        None              => return response,
+    }
+
+    response.headers.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+
+    if body.len() > threshold
+    {
+        if let Ok(compressed) = codec.compress(&body)
+        {
+            response.headers.set(ContentEncoding(vec![if codec == Codec::Gzip { Encoding::Gzip } else { Encoding::EncodingExt(String::from(codec.name())) }]));
+            response.set_mut(compressed);
+
+            return response;
+        }
+    }
+
+    response.set_mut(body);
+
+    response
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    struct Unreadable;
+
+    impl iron::response::WriteBody for Unreadable
+    {
+        fn write_body(&mut self, _res: &mut dyn Write) -> io::Result<()>
+        {
+            Err(io::Error::other("gone"))
+        }
+    }
+
+    fn _settings(codec: Codec, threshold: usize) -> config::Settings
+    {
+        config::Settings { compression: config::CompressionSettings { codec, threshold }, ..config::Settings::default() }
+    }
+
+    fn _body(mut response: Response) -> Vec<u8>
+    {
+        let mut body = Vec::new();
+
+        response.body.take().unwrap().write_body(&mut body).unwrap();
+
+        body
+    }
+
+    #[test]
+    fn codecs_round_trip()
+    {
+        let text = "abc".repeat(1000);
+
+        for codec in [Codec::None, Codec::Gzip, Codec::Zstd]
+        {
+            let packed = codec.compress(text.as_bytes()).unwrap();
+
+            assert_eq!(codec.decompress(&packed).unwrap(), text.as_bytes(), "{}", codec.name());
+        }
+
+        assert!(Codec::Gzip.decompress(b"not gzip").is_err());
+    }
+
+    #[test]
+    fn payloads_pack_above_the_threshold()
+    {
+        let long  = "abc".repeat(1000);
+        let short = String::from("abc");
+
+        for codec in [Codec::Gzip, Codec::Zstd]
+        {
+            let packed = Payload::pack(long.clone(), &_settings(codec, 100), false);
+
+            assert!(matches!(packed, Payload::Packed(c, _) if c == codec));
+            assert!(packed.stored_len() < long.len());
+            assert_eq!(packed.text().unwrap(), long);
+            assert_eq!(packed, Payload::Plain(long.clone()));
+            assert_eq!(serde_json::to_string(&packed).unwrap(), serde_json::to_string(&long).unwrap());
+
+            assert!(matches!(Payload::pack(short.clone(), &_settings(codec, 100), false), Payload::Plain(_)));
+        }
+
+        assert!(matches!(Payload::pack(long, &_settings(Codec::None, 100), false), Payload::Plain(_)));
+    }
+
+    #[test]
+    fn payloads_that_do_not_shrink_stay_plain()
+    {
+        let text = String::from("abcdefghij");
+
+        for codec in [Codec::Gzip, Codec::Zstd]
+        {
+            assert!(matches!(Payload::pack(text.clone(), &_settings(codec, 5), false), Payload::Plain(plain) if plain == text));
+        }
+    }
+
+    #[test]
+    fn responses_compress_above_the_threshold()
+    {
+        let text = "abc".repeat(1000);
+
+        for codec in [Codec::Gzip, Codec::Zstd]
+        {
+            let response = _encode(Some(codec), Response::with((status::Ok, text.clone())), 100);
+
+            assert!(response.headers.get::<ContentEncoding>().is_some());
+            assert_eq!(codec.decompress(&_body(response)).unwrap(), text.as_bytes());
+        }
+
+        let short = _encode(Some(Codec::Gzip), Response::with((status::Ok, text.clone())), 10_000);
+
+        assert!(short.headers.get::<ContentEncoding>().is_none());
+        assert_eq!(_body(short), text.as_bytes());
+
+        let refused = _encode(None, Response::with((status::Ok, text.clone())), 100);
+
+        assert!(refused.headers.get::<ContentEncoding>().is_none());
+        assert_eq!(_body(refused), text.as_bytes());
+    }
+
+    #[test]
+    fn unreadable_bodies_answer_500()
+    {
+        let mut response = Response::with(status::Ok);
+
+        response.body = Some(Box::new(Unreadable));
+
+        let response = _encode(Some(Codec::Gzip), response, 0);
+
+        assert_eq!(response.status, Some(status::InternalServerError));
+        assert!(String::from_utf8(_body(response)).unwrap().contains("\"internal\""));
+    }
+}
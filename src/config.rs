@@ -0,0 +1,591 @@
+use super::*;
+
+use std::path::Path;
+use std::str::FromStr;
+
+pub const USAGE: &str = "Usage: miniq [OPTIONS]
+
+Options:
+    -c, --config <FILE>      TOML settings file            [env: MINIQ_CONFIG]
+        --host <HOST>        address to bind               [env: MINIQ_HOST]
+    -p, --port <PORT>        port to bind                  [env: MINIQ_PORT]
+        --threads <N>        request worker threads        [env: MINIQ_THREADS]
+        --data-dir <DIR>     persistence directory         [env: MINIQ_DATA_DIR]
+        --tls-cert <FILE>    PEM certificate chain         [env: MINIQ_TLS_CERT]
+        --tls-key <FILE>     PEM private key               [env: MINIQ_TLS_KEY]
+        --tls-client-ca <FILE>
+                             CA for client certificates    [env: MINIQ_TLS_CLIENT_CA]
+        --log-level <LEVEL>  debug, info, warn or error    [env: MINIQ_LOG_LEVEL]
+        --log-file <FILE>    JSON-lines log file, rotated by size; stderr if unset
+                                                           [env: MINIQ_LOG_FILE]
+        --shutdown-timeout <SECONDS>
+                             grace period for running requests on SIGTERM/SIGINT
+                                                           [env: MINIQ_SHUTDOWN_TIMEOUT]
+    -h, --help               print this help
+
+Command-line options override environment variables, which override the settings file.";
+
+/// HTTPS listener settings; a `client_ca` turns on client-certificate verification.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings
+{
+    pub cert:      String,
+    pub key:       String,
+    pub client_ca: Option<String>,
+}
+
+/// Defaults applied to every queue.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueLimits
+{
+    /// Messages a queue holds before `push` is refused; unbounded if unset.
+    pub max_messages:        Option<usize>,
+    /// Seconds a message lives when `push` gives no `lifetime`.
+    pub default_lifetime:    f64,
+    /// Seconds a request waits for its reply at most, and a reply queue lives unread when not given.
+    pub reply_timeout:       f64,
+    /// Seconds a `dedup_id` is remembered after the message carrying it was published.
+    pub dedup_window:        f64,
+    /// What `push` does with a `dedup_id` seen within the window.
+    pub dedup:               DedupMode,
+    /// Seconds an open transaction lives without being staged to, committed or rolled back.
+    pub transaction_timeout: f64,
+    /// Operations a transaction may stage; its pushes may stage `messages.max_bytes` of payload in all.
+    pub max_staged:          usize,
+}
+
+impl Default for QueueLimits
+{
+    fn default() -> Self
+    {
+        QueueLimits { max_messages: None, default_lifetime: 6.0, reply_timeout: 30.0, dedup_window: 300.0, dedup: DedupMode::Ignore, transaction_timeout: 60.0, max_staged: 1000 }
+    }
+}
+
+/// Answer to a publish whose `dedup_id` is already known.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode
+{
+    /// Answers the original message as if it had just been published; once the queue no longer
+    /// holds it, with what was published but an empty `data`.
+    Ignore,
+    /// Fails with `409 duplicate`, carrying the original message id.
+    Reject,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageSettings
+{
+    /// Largest request body read, in bytes, before and after undoing its `Content-Encoding`.
+    pub max_bytes:   usize,
+    /// Bytes above which a payload is kept in a file under `spill_dir` instead of in memory; never if unset.
+    pub spill_above: Option<usize>,
+    /// Directory of spilled payloads, cleared at startup; `blobs` inside `data_dir` if unset, or
+    /// `miniq-blobs-<pid>` in the system temp dir, removed at shutdown, without a `data_dir` either.
+    pub spill_dir:   Option<String>,
+}
+
+impl Default for MessageSettings
+{
+    fn default() -> Self
+    {
+        MessageSettings { max_bytes: 16 * 1024 * 1024, spill_above: Some(1024 * 1024), spill_dir: None }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemorySettings
+{
+    /// Approximate bytes all queues may hold before `flow_control` starts; unbounded if unset.
+    pub limit:         Option<usize>,
+    /// What publishers meet once `limit` is reached.
+    pub flow_control:  FlowControl,
+    /// Seconds a blocked publish waits for room before it is refused.
+    pub block_timeout: f64,
+}
+
+impl Default for MemorySettings
+{
+    fn default() -> Self
+    {
+        MemorySettings { limit: None, flow_control: FlowControl::Reject, block_timeout: 5.0 }
+    }
+}
+
+/// Answer to a publish once the broker holds `memory.limit` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl
+{
+    /// Fails with `429 memory_full`.
+    Reject,
+    /// Waits up to `memory.block_timeout` for room, then fails like `Reject`.
+    Block,
+    /// Accepts, keeping new payloads and then the oldest ones on disk until there is room.
+    Page,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionSettings
+{
+    /// How payloads longer than `threshold` are stored.
+    pub codec:     compression::Codec,
+    /// Bytes above which payloads are stored compressed and responses are compressed.
+    pub threshold: usize,
+}
+
+impl Default for CompressionSettings
+{
+    fn default() -> Self
+    {
+        CompressionSettings { codec: compression::Codec::None, threshold: 4096 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSettings
+{
+    /// Principals granted broker-wide `create` and `manage`.
+    pub operators:        Vec<String>,
+    /// Whether any client may create queues.
+    pub anonymous_create: bool,
+    /// Whether a client without a certificate is known by its host, sharing grants, queues and
+    /// transactions with every client of that host, instead of by `host:port`.
+    pub host_principals:  bool,
+}
+
+impl Default for AuthSettings
+{
+    fn default() -> Self
+    {
+        AuthSettings { operators: Vec::new(), anonymous_create: true, host_principals: false }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditSettings
+{
+    /// Entries kept per principal; older ones are dropped first.
+    pub per_principal:  usize,
+    /// Principals with a trail; the one idle longest is dropped to make room for a new one.
+    pub max_principals: usize,
+}
+
+impl Default for AuditSettings
+{
+    fn default() -> Self
+    {
+        AuditSettings { per_principal: 1000, max_principals: 10000 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings
+{
+    pub level:     logging::Level,
+    /// JSON-lines file; stderr if unset.
+    pub file:      Option<String>,
+    /// Size at which `file` is rotated.
+    pub max_bytes: u64,
+    /// Rotated files kept next to `file`.
+    pub keep:      usize,
+}
+
+impl Default for LogSettings
+{
+    fn default() -> Self
+    {
+        LogSettings { level: logging::Level::Info, file: None, max_bytes: 10 * 1024 * 1024, keep: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings
+{
+    pub host:             String,
+    pub port:             u64,
+    pub threads:          usize,
+    pub data_dir:         Option<String>,
+    /// Seconds to wait for running requests on SIGTERM/SIGINT.
+    pub shutdown_timeout: f64,
+    pub queue:            QueueLimits,
+    pub messages:         MessageSettings,
+    pub memory:           MemorySettings,
+    pub compression:      CompressionSettings,
+    pub auth:             AuthSettings,
+    pub log:              LogSettings,
+    pub audit:            AuditSettings,
+    pub tls:              Option<TlsSettings>,
+}
+
+impl Default for Settings
+{
+    fn default() -> Self
+    {
+        Settings
+        {
+            host:             String::from("localhost"),
+            port:             8080,
+            threads:          8,
+            data_dir:         None,
+            shutdown_timeout: 30.0,
+            queue:            QueueLimits::default(),
+            messages:         MessageSettings::default(),
+            memory:           MemorySettings::default(),
+            compression:      CompressionSettings::default(),
+            auth:             AuthSettings::default(),
+            log:              LogSettings::default(),
+            audit:            AuditSettings::default(),
+            tls:              None,
+        }
+    }
+}
+
+impl Settings
+{
+    /// Settings file, then environment, then command line; every problem found is reported.
+    pub fn load(args: Vec<String>) -> Result<Settings, Vec<String>>
+    {
+        let mut errors = Vec::new();
+        let options    = Self::_options(args, &mut errors);
+        let file       = options.get("config").cloned().or_else(|| std::env::var("MINIQ_CONFIG").ok());
+
+        let mut this = match file
+        {
+            Some(path) => match Self::from_file(&path)
+            {
+                Ok(settings) => settings,
+                Err(err)     => return Err(vec![err]),
+            },
+            None => Settings::default(),
+        };
+
+        let mut overrides: HashMap<String, String> = HashMap::new();
+
+        for name in &["host", "port", "threads", "data-dir", "tls-cert", "tls-key", "tls-client-ca", "shutdown-timeout", "log-level", "log-file"]
+        {
+            if let Ok(value) = std::env::var(format!("MINIQ_{}", name.to_uppercase().replace("-", "_")))
+            {
+                overrides.insert(name.to_string(), value);
+            }
+        }
+
+        overrides.extend(options);
+        this._apply(overrides, &mut errors);
+        errors.extend(this.validate());
+
+        if errors.is_empty() { Ok(this) } else { Err(errors) }
+    }
+
+    pub fn from_file(path: &str) -> Result<Settings, String>
+    {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("\"{}\": {}", path, e))?;
+
+        toml::from_str(&content).map_err(|e| format!("\"{}\": {}", path, e))
+    }
+
+    pub fn validate(&self) -> Vec<String>
+    {
+        let mut errors = Vec::new();
+
+        if self.host.is_empty()                                                             { errors.push(String::from("host must not be empty")); }
+        if self.port == 0 || self.port > 65535                                              { errors.push(format!("port {} is out of range 1-65535", self.port)); }
+        if self.threads == 0                                                                { errors.push(String::from("threads must be at least 1")); }
+        if self.queue.max_messages == Some(0)                                               { errors.push(String::from("queue.max_messages must be at least 1")); }
+        if self.queue.max_staged == 0                                                       { errors.push(String::from("queue.max_staged must be at least 1")); }
+        if self.messages.max_bytes == 0                                                     { errors.push(String::from("messages.max_bytes must be at least 1")); }
+        if self.messages.spill_above == Some(0)                                             { errors.push(String::from("messages.spill_above must be at least 1")); }
+        if self.memory.limit == Some(0)                                                     { errors.push(String::from("memory.limit must be at least 1")); }
+        if self.log.max_bytes == 0                                                          { errors.push(String::from("log.max_bytes must be at least 1")); }
+        if self.audit.per_principal == 0                                                    { errors.push(String::from("audit.per_principal must be at least 1")); }
+        if self.audit.max_principals == 0                                                   { errors.push(String::from("audit.max_principals must be at least 1")); }
+
+        _seconds("shutdown_timeout",          self.shutdown_timeout,          true,  &mut errors);
+        _seconds("queue.default_lifetime",    self.queue.default_lifetime,    false, &mut errors);
+        _seconds("queue.reply_timeout",       self.queue.reply_timeout,       false, &mut errors);
+        _seconds("queue.dedup_window",        self.queue.dedup_window,        true,  &mut errors);
+        _seconds("queue.transaction_timeout", self.queue.transaction_timeout, false, &mut errors);
+        _seconds("memory.block_timeout",      self.memory.block_timeout,      true,  &mut errors);
+
+        if let Some(dir) = &self.data_dir
+        {
+            if Path::new(dir).exists() && !Path::new(dir).is_dir() { errors.push(format!("data_dir \"{}\" is not a directory", dir)); }
+        }
+
+        if let Some(dir) = &self.messages.spill_dir
+        {
+            if Path::new(dir).exists() && !Path::new(dir).is_dir() { errors.push(format!("messages.spill_dir \"{}\" is not a directory", dir)); }
+        }
+
+        if let Some(tls) = &self.tls
+        {
+            for path in vec![Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()].into_iter().flatten()
+            {
+                if !Path::new(path).is_file() { errors.push(format!("tls file \"{}\" does not exist", path)); }
+            }
+        }
+
+        errors
+    }
+
+    fn _options(args: Vec<String>, errors: &mut Vec<String>) -> HashMap<String, String>
+    {
+        let mut options = HashMap::new();
+        let mut args    = args.into_iter();
+
+        while let Some(arg) = args.next()
+        {
+            let name = match &*arg
+            {
+                "-c" | "--config" => "config",
+                "-p" | "--port"   => "port",
+                "--host" | "--threads" | "--data-dir" | "--tls-cert" | "--tls-key" | "--tls-client-ca" | "--shutdown-timeout" | "--log-level" | "--log-file" => &arg[2..],
+                _ => { errors.push(format!("unknown option \"{}\"", arg)); continue; },
+            };
+
+            match args.next()
+            {
+                Some(value) => { options.insert(name.to_string(), value); },
+                None        => errors.push(format!("\"{}\" requires a value", arg)),
+            }
+        }
+
+        options
+    }
+
+    fn _apply(&mut self, overrides: HashMap<String, String>, errors: &mut Vec<String>)
+    {
+        for (name, value) in overrides
+        {
+            match &*name
+            {
+                "host"             => self.host     = value,
+                "port"             => if let Some(v) = _parse(&name, &value, errors) { self.port    = v; },
+                "threads"          => if let Some(v) = _parse(&name, &value, errors) { self.threads = v; },
+                "data-dir"         => self.data_dir = Some(value),
+                "shutdown-timeout" => if let Some(v) = _parse(&name, &value, errors) { self.shutdown_timeout = v; },
+                "tls-cert"         => self._tls().cert = value,
+                "tls-key"          => self._tls().key  = value,
+                "tls-client-ca"    => self._tls().client_ca = Some(value),
+                "log-level"        => match value.parse::<logging::Level>() { Ok(level) => self.log.level = level, Err(err) => errors.push(err) },
+                "log-file"         => self.log.file = Some(value),
+                _                  => (),
+            }
+        }
+
+        if let Some(tls) = &self.tls
+        {
+            if tls.cert.is_empty() || tls.key.is_empty() { errors.push(String::from("tls needs both a certificate and a key")); }
+        }
+    }
+
+    fn _tls(&mut self) -> &mut TlsSettings
+    {
+        self.tls.get_or_insert_with(|| TlsSettings { cert: String::new(), key: String::new(), client_ca: None })
+    }
+}
+
+fn _parse<T: FromStr>(name: &str, value: &str, errors: &mut Vec<String>) -> Option<T>
+{
+    match value.parse::<T>()
+    {
+        Ok(v)  => Some(v),
+        Err(_) => { errors.push(format!("{} \"{}\" is not a valid number", name, value)); None },
+    }
+}
+
+/// Durations end up in `Duration::from_secs_f64`, which panics on what is not finite or too large,
+/// so a setting in seconds must be within `0` (when `zero` is allowed) and `model::MAX_LIFETIME`.
+fn _seconds(name: &str, value: f64, zero: bool, errors: &mut Vec<String>)
+{
+    let above = if zero { value >= 0.0 } else { value > 0.0 };
+
+    if !(above && value <= model::MAX_LIFETIME)
+    {
+        let range = if zero { "from 0 to" } else { "above 0 and at most" };
+
+        errors.push(format!("{} must be a number of seconds {} {}", name, range, model::MAX_LIFETIME));
+    }
+}
+
+lazy_static!
+{
+    static ref SETTINGS: RwLock<Settings> = RwLock::new(Settings::default());
+}
+
+pub fn settings() -> Settings
+{
+    SETTINGS.read().unwrap().clone()
+}
+
+/// The installed settings without copying them, for callers that read a field or two.
+pub fn current() -> std::sync::RwLockReadGuard<'static, Settings>
+{
+    SETTINGS.read().unwrap()
+}
+
+pub fn install(settings: Settings)
+{
+    *SETTINGS.write().unwrap() = settings;
+}
+
+pub fn routes() -> router::Router
+{
+    let mut _router = router::Router::new();
+
+    router_add_path(&mut _router, "/new_queue",                                  "post",   &Handler::ClientAndFormdata( &qgatawey::new_queue),         Doc::new("Create a queue").body::<model::QueueName>().answer::<HashMap<String, Queue>>());
+    router_add_path(&mut _router, "/",                                           "get",    &Handler::OnlyClient(        &qgatawey::full_map),          Doc::new("Every queue the caller may read").answer::<HashMap<String, Queue>>());
+    router_add_path(&mut _router, "/sub",                                        "post",   &Handler::ClientAndFormdata( &qgatawey::sub),               Doc::new("Subscribe the caller").body::<model::Subscribe>().answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/unsub",                                      "post",   &Handler::ClientAndFormdata( &qgatawey::unsub),             Doc::new("Unsubscribe the caller").body::<model::QueueName>().answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/pub",                                        "post",   &Handler::ClientAndFormdata( &qgatawey::_pub),              Doc::new("Add a publisher").body::<model::Publisher>().answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/unpub",                                      "post",   &Handler::ClientAndFormdata( &qgatawey::unpub),             Doc::new("Remove a publisher").body::<model::Publisher>().answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/delete_queue",                               "post",   &Handler::ClientAndFormdata( &qgatawey::delete_queue),      Doc::new("Delete a queue and its messages").body::<model::QueueName>().answer::<model::Deleted>());
+    router_add_path(&mut _router, "/push",                                       "post",   &Handler::ClientAndFormdata( &qgatawey::push_in_queue),     Doc::new("Publish a message").body::<model::Push>().answer::<MSG>());
+    router_add_path(&mut _router, "/consume",                                    "post",   &Handler::ClientAndFormdata( &qgatawey::consume),           Doc::new("Take the next message waiting for the caller, `null` when there is none").body::<model::QueueName>().answer::<Option<MSG>>());
+    router_add_path(&mut _router, "/ack",                                        "post",   &Handler::ClientAndFormdata( &qgatawey::ack),               Doc::new("Take one message off a queue for the caller").body::<model::Ack>().answer::<MSG>());
+    router_add_path(&mut _router, "/reply_queue",                                "post",   &Handler::ClientAndFormdata( &qgatawey::reply_queue),       Doc::new("Create an exclusive reply queue").body::<model::ReplyQueue>().answer::<model::QueueName>());
+    router_add_path(&mut _router, "/request",                                    "post",   &Handler::ClientAndFormdata( &qgatawey::request),           Doc::new("Publish a request and wait for its reply").body::<model::Call>().answer::<MSG>());
+    router_add_path(&mut _router, "/transaction",                                "post",   &Handler::OnlyClient(        &qgatawey::begin_transaction), Doc::new("Begin a transaction").answer::<model::TransactionId>());
+    router_add_path(&mut _router, "/transaction/push",                           "post",   &Handler::ClientAndFormdata( &qgatawey::stage_push),        Doc::new("Stage a push in a transaction").body::<model::StagedPush>().answer::<model::Staged>());
+    router_add_path(&mut _router, "/transaction/ack",                            "post",   &Handler::ClientAndFormdata( &qgatawey::stage_ack),         Doc::new("Stage an ack in a transaction").body::<model::StagedAck>().answer::<model::Staged>());
+    router_add_path(&mut _router, "/transaction/commit",                         "post",   &Handler::ClientAndFormdata( &qgatawey::commit),            Doc::new("Apply a transaction, all or nothing").body::<model::TransactionId>().answer::<transaction::Outcome>());
+    router_add_path(&mut _router, "/transaction/rollback",                       "post",   &Handler::ClientAndFormdata( &qgatawey::rollback),          Doc::new("Discard a transaction").body::<model::TransactionId>().answer::<model::Staged>());
+    router_add_path(&mut _router, "/user_log",                                   "get",    &Handler::ClientAndQuery(    &qgatawey::get_user_log),      Doc::new("Audit trail of a principal, newest first").query(audit::Query::PARAMS));
+    router_add_path(&mut _router, "/grant",                                      "post",   &Handler::ClientAndFormdata( &qgatawey::grant),             Doc::new("Grant a permission on a queue").body::<model::Grant>().answer::<acl::Acl>());
+    router_add_path(&mut _router, "/revoke",                                     "post",   &Handler::ClientAndFormdata( &qgatawey::revoke),            Doc::new("Revoke a permission on a queue").body::<model::Grant>().answer::<acl::Acl>());
+    router_add_path(&mut _router, "/role",                                       "post",   &Handler::ClientAndFormdata( &qgatawey::assign_role),       Doc::new("Assign a role to a principal").body::<model::Role>().answer::<Vec<String>>());
+    router_add_path(&mut _router, "/state",                                      "get",    &Handler::OnlyClient(        &qgatawey::dump_state),        Doc::new("Dump every queue"));
+    router_add_path(&mut _router, "/state",                                      "post",   &Handler::ClientAndFormdata( &qgatawey::restore_state),     Doc::new("Restore queues from a dump").body::<model::State>().answer::<model::Restored>());
+    router_add_path(&mut _router, "/metrics",                                    "get",    &Handler::Empty(             &metrics::export),             Doc::new("Prometheus metrics").content("text/plain"));
+    router_add_path(&mut _router, "/healthz",                                    "get",    &Handler::Empty(             &health::healthz),             Doc::new("Liveness probe"));
+    router_add_path(&mut _router, "/readyz",                                     "get",    &Handler::Empty(             &health::readyz),              Doc::new("Readiness probe"));
+    router_add_path(&mut _router, "/info",                                       "get",    &Handler::Empty(             &health::info),                Doc::new("Version, uptime and totals"));
+    router_add_path(&mut _router, "/openapi.json",                               "get",    &Handler::Empty(             &openapi::serve),              Doc::new("This document"));
+    router_add_path(&mut _router, "/queues",                                     "get",    &Handler::ClientAndQuery(    &qgatawey::list_queues),       Doc::new("Page of queue summaries").query(listing::QueueQuery::PARAMS));
+    router_add_path(&mut _router, "/queue",                                      "get",    &Handler::ClientAndQuery(    &qgatawey::queue_info),        Doc::new("Metadata of one queue").query(&["name"]));
+    router_add_path(&mut _router, "/messages",                                   "get",    &Handler::ClientAndQuery(    &qgatawey::peek_messages),     Doc::new("Page of a queue's messages").query(listing::MessageQuery::PARAMS));
+    router_add_path(&mut _router, "/v2/queues",                                  "get",    &Handler::ClientAndQuery(    &qgatawey::list_queues),       Doc::new("Page of queue summaries").query(listing::QueueQuery::PARAMS));
+    router_add_path(&mut _router, "/v2/queues",                                  "post",   &Handler::ClientAndPath(     &v2::create_queue),            Doc::new("Create a queue").body::<model::QueueName>().status(status::Created));
+    router_add_path(&mut _router, "/v2/queues/:name",                            "get",    &Handler::ClientAndPath(     &v2::queue_info),              Doc::new("Metadata of one queue"));
+    router_add_path(&mut _router, "/v2/queues/:name",                            "delete", &Handler::ClientAndPath(     &v2::delete_queue),            Doc::new("Delete a queue and its messages").answer::<model::Deleted>());
+    router_add_path(&mut _router, "/v2/queues/:name/messages",                   "get",    &Handler::ClientAndPath(     &v2::list_messages),           Doc::new("Page of a queue's messages").query(&listing::MessageQuery::PARAMS[1..]));
+    router_add_path(&mut _router, "/v2/queues/:name/messages",                   "post",   &Handler::ClientAndPath(     &v2::publish),                 Doc::new("Publish a message").body::<model::Message>().answer::<MSG>().status(status::Created));
+    router_add_path(&mut _router, "/v2/queues/:name/messages/next",              "post",   &Handler::ClientAndPath(     &v2::consume),                 Doc::new("Take the next message waiting for the caller, `null` when there is none").answer::<Option<MSG>>());
+    router_add_path(&mut _router, "/v2/queues/:name/messages/:id",               "delete", &Handler::ClientAndPath(     &v2::ack),                     Doc::new("Take one message off a queue for the caller").answer::<MSG>());
+    router_add_path(&mut _router, "/v2/queues/:name/messages/:id/data",          "get",    &Handler::ClientAndPath(     &v2::message_data),            Doc::new("Payload of one message, streamed when spilled to disk").content("text/plain"));
+    router_add_path(&mut _router, "/v2/queues/:name/requests",                   "post",   &Handler::ClientAndPath(     &v2::request),                 Doc::new("Publish a request and wait for its reply").body::<model::Message>().answer::<MSG>().query(&["timeout"]));
+    router_add_path(&mut _router, "/v2/queues/:name/subscribers/:client",        "put",    &Handler::ClientAndPath(     &v2::put_subscriber),          Doc::new("Subscribe a client, `me` for the caller").query(&["selector"]).answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/v2/queues/:name/subscribers/:client",        "delete", &Handler::ClientAndPath(     &v2::delete_subscriber),       Doc::new("Unsubscribe a client, `me` for the caller").answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/v2/queues/:name/publishers/:client",         "put",    &Handler::ClientAndPath(     &v2::put_publisher),           Doc::new("Add a publisher, `me` for the caller").answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/v2/queues/:name/publishers/:client",         "delete", &Handler::ClientAndPath(     &v2::delete_publisher),        Doc::new("Remove a publisher, `me` for the caller").answer::<Vec<Client>>());
+    router_add_path(&mut _router, "/v2/queues/:name/acl/:principal/:permission", "put",    &Handler::ClientAndPath(     &v2::put_grant),               Doc::new("Grant a permission on a queue").answer::<acl::Acl>());
+    router_add_path(&mut _router, "/v2/queues/:name/acl/:principal/:permission", "delete", &Handler::ClientAndPath(     &v2::delete_grant),            Doc::new("Revoke a permission on a queue").answer::<acl::Acl>());
+    router_add_path(&mut _router, "/v2/roles/:principal/:role",                  "put",    &Handler::ClientAndPath(     &v2::put_role),                Doc::new("Assign a role to a principal").answer::<Vec<String>>());
+    router_add_path(&mut _router, "/v2/transactions",                            "post",   &Handler::ClientAndPath(     &v2::begin_transaction),       Doc::new("Begin a transaction").answer::<model::TransactionId>().status(status::Created));
+    router_add_path(&mut _router, "/v2/transactions/:id",                        "delete", &Handler::ClientAndPath(     &v2::rollback),                Doc::new("Roll back a transaction").answer::<model::Staged>());
+    router_add_path(&mut _router, "/v2/transactions/:id/messages",               "post",   &Handler::ClientAndPath(     &v2::stage_push),              Doc::new("Stage a push in a transaction").body::<model::Push>().answer::<model::Staged>());
+    router_add_path(&mut _router, "/v2/transactions/:id/acks",                   "post",   &Handler::ClientAndPath(     &v2::stage_ack),               Doc::new("Stage an ack in a transaction").body::<model::Ack>().answer::<model::Staged>());
+    router_add_path(&mut _router, "/v2/transactions/:id/commit",                 "post",   &Handler::ClientAndPath(     &v2::commit),                  Doc::new("Apply a transaction, all or nothing").answer::<transaction::Outcome>());
+    router_add_path(&mut _router, "/v2/reply_queues",                            "post",   &Handler::ClientAndPath(     &v2::create_reply_queue),      Doc::new("Create an exclusive reply queue").body::<model::ReplyQueue>().answer::<model::QueueName>().status(status::Created));
+    router_add_path(&mut _router, "/v2/state",                                   "get",    &Handler::OnlyClient(        &qgatawey::dump_state),        Doc::new("Dump every queue"));
+    router_add_path(&mut _router, "/v2/state",                                   "put",    &Handler::ClientAndFormdata( &qgatawey::restore_state),     Doc::new("Restore queues from a dump").body::<model::State>().answer::<model::Restored>());
+    router_add_path(&mut _router, "/console",                                    "get",    &Handler::ClientAndRequest(  &console::index),              Doc::new("Web console").content("text/html"));
+    router_add_path(&mut _router, "/console/queues",                             "post",   &Handler::ClientAndRequest(  &console::create),             Doc::new("Web console: create a queue").content("text/html"));
+    router_add_path(&mut _router, "/console/queues/:name",                       "get",    &Handler::ClientAndRequest(  &console::queue),              Doc::new("Web console: one queue").content("text/html"));
+    router_add_path(&mut _router, "/console/queues/:name/purge",                 "post",   &Handler::ClientAndRequest(  &console::purge),              Doc::new("Web console: purge a queue").content("text/html"));
+    router_add_path(&mut _router, "/console/queues/:name/publish",               "post",   &Handler::ClientAndRequest(  &console::publish),            Doc::new("Web console: publish a message").content("text/html"));
+    
+    _router
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn _errors<F: FnOnce(&mut Settings)>(change: F) -> Vec<String>
+    {
+        let mut settings = Settings::default();
+
+        change(&mut settings);
+        settings.validate()
+    }
+
+    #[test]
+    fn defaults_are_valid()
+    {
+        assert_eq!(Settings::default().validate(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn durations_must_be_finite_and_bounded()
+    {
+        type Field = fn(&mut Settings) -> &mut f64;
+
+        let fields: [(&str, bool, Field); 6] =
+        [
+            ("shutdown_timeout",          true,  |s| &mut s.shutdown_timeout),
+            ("queue.default_lifetime",    false, |s| &mut s.queue.default_lifetime),
+            ("queue.reply_timeout",       false, |s| &mut s.queue.reply_timeout),
+            ("queue.dedup_window",        true,  |s| &mut s.queue.dedup_window),
+            ("queue.transaction_timeout", false, |s| &mut s.queue.transaction_timeout),
+            ("memory.block_timeout",      true,  |s| &mut s.memory.block_timeout),
+        ];
+
+        for (name, zero, field) in fields.iter()
+        {
+            for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300, model::MAX_LIFETIME + 1.0, -1.0]
+            {
+                let errors = _errors(|settings| *field(settings) = bad);
+
+                assert!(errors.len() == 1 && errors[0].starts_with(name), "{} = {}: {:?}", name, bad, errors);
+            }
+
+            assert_eq!(_errors(|settings| *field(settings) = 0.0).is_empty(), *zero, "{} = 0", name);
+            assert!(_errors(|settings| *field(settings) = model::MAX_LIFETIME).is_empty(), "{} at the maximum", name);
+        }
+    }
+
+    #[test]
+    fn counts_must_be_positive()
+    {
+        assert_eq!(_errors(|settings| settings.port = 0), vec![String::from("port 0 is out of range 1-65535")]);
+        assert_eq!(_errors(|settings| settings.threads = 0), vec![String::from("threads must be at least 1")]);
+        assert_eq!(_errors(|settings| settings.queue.max_staged = 0), vec![String::from("queue.max_staged must be at least 1")]);
+        assert_eq!(_errors(|settings| settings.memory.limit = Some(0)), vec![String::from("memory.limit must be at least 1")]);
+    }
+
+    #[test]
+    fn every_problem_is_reported()
+    {
+        let errors = _errors(|settings|
+        {
+            settings.host               = String::new();
+            settings.queue.reply_timeout = f64::INFINITY;
+            settings.messages.spill_dir = Some(String::from(file!()));
+        });
+
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+    }
+
+    #[test]
+    fn unknown_options_are_reported()
+    {
+        let mut errors = Vec::new();
+        let options    = Settings::_options(vec![String::from("--port"), String::from("9000"), String::from("--colour"), String::from("--threads")], &mut errors);
+
+        assert_eq!(options.get("port"), Some(&String::from("9000")));
+        assert_eq!(errors, vec![String::from("unknown option \"--colour\""), String::from("\"--threads\" requires a value")]);
+    }
+}
@@ -0,0 +1,313 @@
+use super::*;
+use typed_html::dom::DOMTree;
+use typed_html::elements::FlowContent;
+use iron::url::{form_urlencoded, percent_encoding::{percent_decode, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET}, Url};
+
+const RECENT:  usize = 20;
+/// Characters of each payload the queue page shows.
+const PREVIEW: usize = 200;
+const STYLE:  &str  = "body{font-family:sans-serif;margin:2em}table{border-collapse:collapse}\
+                       td,th{border:1px solid #ccc;padding:.3em .6em;text-align:left}\
+                       .error{color:#b00}form{margin:1em 0}";
+
+fn _link(name: &str) -> String
+{
+    format!("/console/queues/{}", utf8_percent_encode(name, PATH_SEGMENT_ENCODE_SET))
+}
+
+fn _name(request: &Request) -> String
+{
+    let raw = request.extensions.get::<Router>().and_then(|params| params.find("name")).unwrap_or("");
+
+    percent_decode(raw.as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+/// The `Host` the request was sent to, lowercased.
+fn _host(request: &Request) -> Option<String>
+{
+    request.headers.get_raw("Host").and_then(|values| values.first()).and_then(|value| std::str::from_utf8(value).ok()).map(|host| host.to_lowercase())
+}
+
+/// Refuses form posts a browser sent from another site: their `Origin`, or `Referer` when there is
+/// none, must name the host the request was sent to. Clients that send neither are not browsers.
+fn _same_origin(request: &Request) -> Result<(), QueueError>
+{
+    let source = match request.headers.get_raw("Origin").or_else(|| request.headers.get_raw("Referer")).and_then(|values| values.first())
+    {
+        Some(source) => String::from_utf8_lossy(source).into_owned(),
+        None         => return Ok(()),
+    };
+    let origin = Url::parse(&source).ok().and_then(|url| url.host_str().map(|host| match url.port()
+    {
+        Some(port) => format!("{}:{}", host, port),
+        None       => String::from(host),
+    }));
+
+    match (origin, _host(request))
+    {
+        (Some(origin), Some(host)) if origin.to_lowercase() == host => Ok(()),
+        _                                                          => Err(QueueError::Forbidden(format!("cross-site form post from \"{}\" refused", source))),
+    }
+}
+
+/// The posted form, from a body of at most `messages.max_bytes`.
+fn _form(request: &mut Request) -> Result<urlencoded::QueryMap, QueueError>
+{
+    _same_origin(request)?;
+
+    let body     = compression::read_body(request, config::settings().messages.max_bytes)?;
+    let mut form = urlencoded::QueryMap::new();
+
+    for (key, value) in form_urlencoded::parse(&body)
+    {
+        form.entry(key.into_owned()).or_default().push(value.into_owned());
+    }
+
+    Ok(form)
+}
+
+fn _field(form: &urlencoded::QueryMap, name: &str) -> Option<String>
+{
+    form.get(name).and_then(|values| values.first()).cloned().filter(|value| !value.is_empty())
+}
+
+fn _time(time: SystemTime) -> String
+{
+    let time: DateTime<Utc> = time.into();
+
+    format!("{}", time.format(model::TIME_FORMAT))
+}
+
+fn _redirect(location: String) -> IronResult<Response>
+{
+    let mut response = Response::new();
+
+    response.set_mut(status::SeeOther);
+    response.headers.set(iron::headers::Location(location));
+
+    Ok(response)
+}
+
+/// The page, answered with the status of `error` when there is one.
+fn _page(title: &str, error: Option<QueueError>, content: Vec<Box<dyn FlowContent<String>>>) -> Response
+{
+    let status = error.as_ref().map_or(status::Ok, QueueError::status);
+    let doc: DOMTree<String> = html!(
+        <html>
+            <head>
+                <title>{ text!("miniq: {}", title) }</title>
+                <style>{ text!(STYLE) }</style>
+            </head>
+            <body>
+                <p><a href="/console">"miniq console"</a></p>
+                <h1>{ text!(title) }</h1>
+                { error.map(|err| html!(<p class="error">{ text!(err.to_string()) }</p>)) }
+                { content }
+            </body>
+        </html>
+    );
+
+    Response::html(format!("<!DOCTYPE html>\n{}", doc), status)
+}
+
+/// Queues the client may read, with depth and messages published in the last minute.
+fn _index(client: &Client, error: Option<QueueError>) -> Response
+{
+    let now      = SystemTime::now();
+    let mut rows = qgatawey::queues().values().filter_map(|q|
+    {
+        let queue = q.lock().unwrap();
+
+        if !queue.acl.allows(client, acl::Permission::ReadMetadata) { return None; }
+
+        Some((queue.name.clone(), queue.depth(now), metrics::published_last_minute(&queue.name, now), queue.subscribers.len(), queue.publishers.len()))
+    }).collect::<Vec<(String, usize, u64, usize, usize)>>();
+
+    rows.sort();
+
+    _page("Queues", error, vec![
+        html!(
+            <table>
+                <tr><th>"Queue"</th><th>"Depth"</th><th>"Published / min"</th><th>"Subscribers"</th><th>"Publishers"</th></tr>
+                { rows.into_iter().map(|(name, depth, rate, subscribers, publishers)| html!(
+                    <tr>
+                        <td><a href={ _link(&name) }>{ text!(name) }</a></td>
+                        <td>{ text!("{}", depth) }</td>
+                        <td>{ text!("{}", rate) }</td>
+                        <td>{ text!("{}", subscribers) }</td>
+                        <td>{ text!("{}", publishers) }</td>
+                    </tr>
+                )) }
+            </table>
+        ),
+        html!(
+            <form method="post" action="/console/queues">
+                <input type="text" name="name" placeholder="queue name"/>
+                <button type="submit">"Create queue"</button>
+            </form>
+        ),
+    ])
+}
+
+/// The start of `message`'s payload, when the viewer may subscribe; spilled payloads are read only
+/// that far.
+fn _preview(message: &MSG, payloads: bool) -> String
+{
+    if !payloads { return String::from("(requires subscribe)"); }
+
+    message.data.preview(PREVIEW).unwrap_or_else(|err| format!("(unreadable: {})", err))
+}
+
+/// What the queue page shows, copied out under the queue lock.
+struct Snapshot
+{
+    name:        String,
+    depth:       usize,
+    held:        usize,
+    publishers:  Vec<Client>,
+    subscribers: Vec<Client>,
+    recent:      Vec<MSG>,
+    payloads:    bool,
+}
+
+fn _queue(client: &Client, name: &str, error: Option<QueueError>) -> Response
+{
+    let now   = SystemTime::now();
+    let queue = match qgatawey::broker().update(name, |queue|
+    {
+        queue.acl.check(client, acl::Permission::ReadMetadata, &queue.name)?;
+
+        Ok(Snapshot
+        {
+            name:        queue.name.clone(),
+            depth:       queue.depth(now),
+            held:        queue.data.len(),
+            publishers:  queue.publishers.clone(),
+            subscribers: queue.subscribers.clone(),
+            recent:      queue.data.iter().rev().take(RECENT).cloned().collect(),
+            payloads:    queue.acl.allows(client, acl::Permission::Subscribe),
+        })
+    })
+    {
+        Ok(queue) => queue,
+        Err(err)  => return _index(client, Some(err)),
+    };
+
+    let link     = _link(&queue.name);
+    let payloads = queue.payloads;
+    let people = |title: &str, clients: &Vec<Client>| -> Box<dyn FlowContent<String>> { html!(
+        <div>
+            <h2>{ text!(title) }</h2>
+            <ul>{ clients.iter().map(|c| html!(<li>{ text!(c.to_string()) }</li>)) }</ul>
+        </div>
+    ) };
+
+    _page(&queue.name.clone(), error, vec![
+        html!(<p>{ text!("{} active messages, {} held", queue.depth, queue.held) }</p>),
+        people("Publishers",  &queue.publishers),
+        people("Subscribers", &queue.subscribers),
+        html!(
+            <div>
+                <h2>{ text!("Recent messages (last {})", RECENT) }</h2>
+                <table>
+                    <tr><th>"Created"</th><th>"Sender"</th><th>"Priority"</th><th>"Active"</th><th>"Data"</th></tr>
+                    { queue.recent.into_iter().map(|message| html!(
+                        <tr>
+                            <td>{ text!(_time(message.created)) }</td>
+                            <td>{ text!(message.sender.to_string()) }</td>
+                            <td>{ text!("{}", message.priority) }</td>
+                            <td>{ text!(if message.active && message.lifetime > now { "yes" } else { "no" }) }</td>
+                            <td>{ text!(_preview(&message, payloads)) }</td>
+                        </tr>
+                    )) }
+                </table>
+            </div>
+        ),
+        html!(
+            <form method="post" action={ format!("{}/publish", link) }>
+                <h2>"Publish a test message"</h2>
+                <textarea name="data" placeholder="data"></textarea>
+                <input type="number" name="priority" placeholder="priority" min="0"/>
+                <input type="number" name="lifetime" placeholder="lifetime, seconds" min="0" step="any"/>
+                <button type="submit">"Publish"</button>
+            </form>
+        ),
+        html!(
+            <form method="post" action={ format!("{}/purge", link) }>
+                <button type="submit">"Purge all messages"</button>
+            </form>
+        ),
+    ])
+}
+
+pub fn index(client: Client, _request: &mut Request) -> IronResult<Response>
+{
+    Ok(_index(&client, None))
+}
+
+pub fn queue(client: Client, request: &mut Request) -> IronResult<Response>
+{
+    Ok(_queue(&client, &_name(request), None))
+}
+
+pub fn create(client: Client, request: &mut Request) -> IronResult<Response>
+{
+    let form = match _form(request)
+    {
+        Ok(form) => form,
+        Err(err) => return Ok(_index(&client, Some(err))),
+    };
+    let name = match _field(&form, "name")
+    {
+        Some(name) => name,
+        None       => return Ok(_index(&client, Some(QueueError::Invalid(String::from("\"name\" is required"))))),
+    };
+
+    match qgatawey::broker().create(&client, &name)
+    {
+        Ok(_)    => _redirect(_link(&name)),
+        Err(err) => Ok(_index(&client, Some(err))),
+    }
+}
+
+pub fn purge(client: Client, request: &mut Request) -> IronResult<Response>
+{
+    let name = _name(request);
+
+    if let Err(err) = _same_origin(request) { return Ok(_queue(&client, &name, Some(err))); }
+
+    match qgatawey::broker().update(&name, |queue| queue.purge(&client))
+    {
+        Ok(_)    => _redirect(_link(&name)),
+        Err(err) => Ok(_queue(&client, &name, Some(err))),
+    }
+}
+
+pub fn publish(client: Client, request: &mut Request) -> IronResult<Response>
+{
+    let name     = _name(request);
+    let form     = match _form(request)
+    {
+        Ok(form) => form,
+        Err(err) => return Ok(_queue(&client, &name, Some(err))),
+    };
+    let data     = _field(&form, "data").unwrap_or_default();
+    let priority = match _field(&form, "priority").map(|v| v.parse::<usize>().ok())
+    {
+        None          => None,
+        Some(Some(v)) => Some(v),
+        Some(None)    => return Ok(_queue(&client, &name, Some(QueueError::Invalid(String::from("\"priority\" must be a whole number of at least 0"))))),
+    };
+    let lifetime = match _field(&form, "lifetime").map(|v| v.parse::<f64>().ok().filter(|v| *v > 0.0 && *v <= model::MAX_LIFETIME))
+    {
+        None          => None,
+        Some(Some(v)) => Some(v),
+        Some(None)    => return Ok(_queue(&client, &name, Some(QueueError::Invalid(format!("\"lifetime\" must be a positive number of at most {}", model::MAX_LIFETIME))))),
+    };
+
+    match qgatawey::broker().publish(&client, &name, data, lifetime, priority)
+    {
+        Ok(_)    => _redirect(_link(&name)),
+        Err(err) => Ok(_queue(&client, &name, Some(err))),
+    }
+}
@@ -0,0 +1,198 @@
+use super::*;
+
+
+/// Every error a route can answer with; `code()` is the machine-readable name sent to clients.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueError
+{
+    /// Request body could not be read or is not JSON.
+    BadRequest(String),
+    NotFound(String),
+    AlreadyExists(String),
+    Forbidden(String),
+    /// Well-formed request with missing or unacceptable fields.
+    Invalid(String),
+    /// Request body whose fields failed `model::parse`, as `(field, problem)` pairs.
+    InvalidFields(model::FieldErrors),
+    QueueFull(String),
+    Expired(String),
+    /// Broker is shutting down.
+    Unavailable(String),
+    /// Nothing arrived in time, e.g. no reply to a request.
+    Timeout(String),
+    /// Publish whose `dedup_id` was already seen; carries the original message id.
+    Duplicate(u64),
+    /// Request body over `messages.max_bytes`, or a transaction staging past `queue.max_staged`.
+    TooLarge(String),
+    /// Publish refused by flow control: the broker holds `memory.limit` bytes.
+    MemoryFull(String),
+    /// The broker failed to build an answer, e.g. to read a body it was compressing.
+    Internal(String),
+}
+
+
+impl QueueError
+{
+    pub fn status(&self) -> status::Status
+    {
+        match self
+        {
+            QueueError::BadRequest(_)    => status::BadRequest,
+            QueueError::NotFound(_)      => status::NotFound,
+            QueueError::AlreadyExists(_) => status::Conflict,
+            QueueError::Forbidden(_)     => status::Forbidden,
+            QueueError::Invalid(_)       => status::UnprocessableEntity,
+            QueueError::InvalidFields(_) => status::UnprocessableEntity,
+            QueueError::QueueFull(_)     => status::TooManyRequests,
+            QueueError::Expired(_)       => status::Gone,
+            QueueError::Unavailable(_)   => status::ServiceUnavailable,
+            QueueError::Timeout(_)       => status::GatewayTimeout,
+            QueueError::Duplicate(_)     => status::Conflict,
+            QueueError::TooLarge(_)      => status::PayloadTooLarge,
+            QueueError::MemoryFull(_)    => status::TooManyRequests,
+            QueueError::Internal(_)      => status::InternalServerError,
+        }
+    }
+
+    pub fn code(&self) -> &'static str
+    {
+        match self
+        {
+            QueueError::BadRequest(_)    => "bad_request",
+            QueueError::NotFound(_)      => "not_found",
+            QueueError::AlreadyExists(_) => "already_exists",
+            QueueError::Forbidden(_)     => "forbidden",
+            QueueError::Invalid(_)       => "invalid",
+            QueueError::InvalidFields(_) => "invalid",
+            QueueError::QueueFull(_)     => "queue_full",
+            QueueError::Expired(_)       => "expired",
+            QueueError::Unavailable(_)   => "unavailable",
+            QueueError::Timeout(_)       => "timeout",
+            QueueError::Duplicate(_)     => "duplicate",
+            QueueError::TooLarge(_)      => "too_large",
+            QueueError::MemoryFull(_)    => "memory_full",
+            QueueError::Internal(_)      => "internal",
+        }
+    }
+
+    pub fn message(&self) -> String
+    {
+        match self
+        {
+            QueueError::BadRequest(txt)       => txt.clone(),
+            QueueError::NotFound(txt)         => txt.clone(),
+            QueueError::AlreadyExists(txt)    => txt.clone(),
+            QueueError::Forbidden(txt)        => txt.clone(),
+            QueueError::Invalid(txt)          => txt.clone(),
+            QueueError::InvalidFields(fields) => fields.iter().map(|(field, problem)| format!("\"{}\" {}", field, problem)).collect::<Vec<String>>().join("; "),
+            QueueError::QueueFull(txt)        => txt.clone(),
+            QueueError::Expired(txt)          => txt.clone(),
+            QueueError::Unavailable(txt)      => txt.clone(),
+            QueueError::Timeout(txt)          => txt.clone(),
+            QueueError::Duplicate(id)         => format!("duplicate of message {}", id),
+            QueueError::TooLarge(txt)         => txt.clone(),
+            QueueError::MemoryFull(txt)       => txt.clone(),
+            QueueError::Internal(txt)         => txt.clone(),
+        }
+    }
+
+    pub fn response(&self) -> Response
+    {
+        Response::json(json::JsonValue::from(self.clone()), self.status())
+    }
+}
+
+
+impl std::convert::From<QueueError> for json::JsonValue
+{
+    fn from(err: QueueError) -> Self
+    {
+        let fields = match &err
+        {
+            QueueError::InvalidFields(fields) => Some(fields.iter().cloned().collect()),
+            _                                 => None,
+        };
+
+        let original_id = match &err
+        {
+            QueueError::Duplicate(id) => Some(*id),
+            _                         => None,
+        };
+
+        model::to_json(&model::ErrorBody { error: err.message(), code: String::from(err.code()), fields, original_id })
+    }
+}
+
+
+impl fmt::Display for QueueError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{}", self.message())
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn every_error_has_its_status_and_code()
+    {
+        let cases =
+        [
+            (QueueError::BadRequest(String::new()),    400, "bad_request"),
+            (QueueError::NotFound(String::new()),      404, "not_found"),
+            (QueueError::AlreadyExists(String::new()), 409, "already_exists"),
+            (QueueError::Forbidden(String::new()),     403, "forbidden"),
+            (QueueError::Invalid(String::new()),       422, "invalid"),
+            (QueueError::InvalidFields(Vec::new()),    422, "invalid"),
+            (QueueError::QueueFull(String::new()),     429, "queue_full"),
+            (QueueError::Expired(String::new()),       410, "expired"),
+            (QueueError::Unavailable(String::new()),   503, "unavailable"),
+            (QueueError::Timeout(String::new()),       504, "timeout"),
+            (QueueError::Duplicate(7),                 409, "duplicate"),
+            (QueueError::TooLarge(String::new()),      413, "too_large"),
+            (QueueError::MemoryFull(String::new()),    429, "memory_full"),
+            (QueueError::Internal(String::new()),      500, "internal"),
+        ];
+
+        for (err, status, code) in cases.iter()
+        {
+            assert_eq!(err.status().to_u16(), *status, "{:?}", err);
+            assert_eq!(err.code(), *code, "{:?}", err);
+            assert_eq!(err.response().status, Some(err.status()), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn body_carries_message_and_code()
+    {
+        let body = json::JsonValue::from(QueueError::NotFound(String::from("queue \"a\" not found")));
+
+        assert_eq!(body, json::object!{"error" => "queue \"a\" not found", "code" => "not_found"});
+    }
+
+    #[test]
+    fn invalid_fields_are_listed()
+    {
+        let err  = QueueError::InvalidFields(vec![(String::from("name"), String::from("must not be empty")), (String::from("priority"), String::from("must be at most 9"))]);
+        let body = json::JsonValue::from(err.clone());
+
+        assert_eq!(err.to_string(), "\"name\" must not be empty; \"priority\" must be at most 9");
+        assert_eq!(body["fields"], json::object!{"name" => "must not be empty", "priority" => "must be at most 9"});
+        assert!(body["original_id"].is_null());
+    }
+
+    #[test]
+    fn duplicate_names_the_original()
+    {
+        let body = json::JsonValue::from(QueueError::Duplicate(42));
+
+        assert_eq!(body["original_id"], 42);
+        assert_eq!(body["error"], "duplicate of message 42");
+        assert!(body["fields"].is_null());
+    }
+}
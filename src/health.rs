@@ -0,0 +1,84 @@
+//! Liveness, readiness and build information for orchestration probes.
+
+use super::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static LOADED:    AtomicBool = AtomicBool::new(false);
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+lazy_static!
+{
+    static ref STARTED: Instant = Instant::now();
+}
+
+/// Call first thing in `main`; uptime is counted from here.
+pub fn started()
+{
+    lazy_static::initialize(&STARTED);
+}
+
+/// Call once persisted queues (if any) have been restored.
+pub fn loaded()
+{
+    LOADED.store(true, Ordering::SeqCst);
+}
+
+/// Call once the listener is bound.
+pub fn listening()
+{
+    LISTENING.store(true, Ordering::SeqCst);
+}
+
+/// Resident set size from `/proc/self/status`; `None` where that is not available.
+fn _rss_bytes() -> Option<u64>
+{
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line   = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib    = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+
+    Some(kib * 1024)
+}
+
+pub fn healthz() -> IronResult<Response>
+{
+    Ok(Response::json(json::object!{"status" => "ok"}, status::Ok))
+}
+
+pub fn readyz() -> IronResult<Response>
+{
+    let loaded    = LOADED.load(Ordering::SeqCst);
+    let listening = LISTENING.load(Ordering::SeqCst);
+    let ready     = loaded && listening;
+    let body      = json::object!
+    {
+        "status"    => if ready { "ready" } else { "starting" },
+        "loaded"    => loaded,
+        "listening" => listening,
+    };
+
+    Ok(Response::json(body, if ready { status::Ok } else { status::ServiceUnavailable }))
+}
+
+pub fn info() -> IronResult<Response>
+{
+    let (queues, messages) =
+    {
+        let qs = qgatawey::queues();
+
+        (qs.len(), qs.values().map(|q| q.lock().unwrap().data.len()).sum::<usize>())
+    };
+
+    let body = json::object!
+    {
+        "name"           => env!("CARGO_PKG_NAME"),
+        "version"        => env!("CARGO_PKG_VERSION"),
+        "uptime_seconds" => STARTED.elapsed().as_secs_f64(),
+        "features"       => json::object!{"tls" => cfg!(feature = "tls")},
+        "queues"         => queues,
+        "messages"       => messages,
+        "rss_bytes"      => _rss_bytes(),
+        "memory"         => json::object!{"used_bytes" => qgatawey::broker().memory.used(), "limit_bytes" => config::settings().memory.limit},
+    };
+
+    Ok(Response::json(body, status::Ok))
+}
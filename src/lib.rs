@@ -0,0 +1,116 @@
+//! miniq: a small in-memory message broker, embeddable as a library or served over HTTP by the `miniq` binary.
+//!
+//! ```
+//! let broker   = miniq::Broker::new();
+//! let producer = miniq::Client::new(String::from("127.0.0.1"), 5000);
+//! let consumer = miniq::Client::new(String::from("127.0.0.1"), 5001);
+//!
+//! broker.create(&producer, "jobs").unwrap();
+//! broker.subscribe(&consumer, "jobs").unwrap();
+//! broker.publish(&producer, "jobs", String::from("hello"), None, None).unwrap();
+//!
+//! assert_eq!(broker.consume(&consumer, "jobs").unwrap().map(|message| message.data.to_string()), Some(String::from("hello")));
+//! assert!(broker.consume(&consumer, "jobs").unwrap().is_none());
+//! ```
+
+#![recursion_limit = "512"]
+
+extern crate iron;
+extern crate hyper;
+extern crate json;
+extern crate chrono;
+extern crate router;
+#[macro_use]
+extern crate typed_html;
+extern crate urlencoded;
+extern crate toml;
+extern crate signal_hook;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "tls")]
+extern crate openssl;
+#[macro_use]
+extern crate mime;
+#[macro_use]
+extern crate lazy_static;
+extern crate flate2;
+extern crate zstd;
+
+use iron::prelude::*;
+use iron::{status, Iron};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use router::{Router};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::fmt;
+use std::time::{SystemTime, Duration, Instant};
+use std::thread;
+use chrono::offset::Utc;
+use chrono::DateTime;
+use schemars::JsonSchema;
+
+mod client;
+mod message;
+mod error;
+mod queue;
+mod broker;
+mod server;
+
+pub use client::Client;
+pub use message::{Payload, Properties, MSG};
+pub use error::QueueError;
+pub use queue::Queue;
+pub use broker::Broker;
+pub use openapi::Doc;
+pub use server::{router_add_path, ApplicationResponse, Handler, Path, Server};
+
+pub mod acl;
+pub mod model;
+pub mod openapi;
+pub mod qgatawey;
+pub mod v2;
+pub mod console;
+pub mod listing;
+pub mod audit;
+pub mod logging;
+pub mod metrics;
+pub mod health;
+pub mod shutdown;
+pub mod persistence;
+pub mod compression;
+pub mod blob;
+pub mod memory;
+pub mod selector;
+pub mod transaction;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod config;
+
+
+/// Fixtures the unit tests of several modules share.
+#[cfg(test)]
+mod testing
+{
+    use super::*;
+
+    /// A client on `127.0.0.1`, told apart from the others by `port`.
+    pub fn client(port: u64) -> Client
+    {
+        Client::new(String::from("127.0.0.1"), port)
+    }
+
+    /// A broker with `queues`, each created and subscribed to by `client(1)`, which it returns.
+    pub fn broker(queues: &[&str]) -> (Broker, Client)
+    {
+        let broker = Broker::new();
+        let client = client(1);
+
+        for name in queues
+        {
+            broker.create(&client, name).unwrap();
+            broker.subscribe(&client, name).unwrap();
+        }
+
+        (broker, client)
+    }
+}
@@ -0,0 +1,315 @@
+//! Read-only views over queues and their messages for the listing routes.
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sort
+{
+    Name,
+    Depth,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueQuery
+{
+    pub prefix:     Option<String>,
+    pub sort:       Sort,
+    pub descending: bool,
+    pub offset:     usize,
+    pub limit:      usize,
+}
+
+impl QueueQuery
+{
+    /// Query string parameters `parse` reads, for `/openapi.json`.
+    pub const PARAMS: &'static [&'static str] = &["prefix", "sort", "order", "cursor", "limit"];
+
+    /// `sort` is `name` (default) or `depth`, `order` is `asc` or `desc`; `cursor` is the `next` of the previous page.
+    pub fn parse<F: Fn(&str) -> Option<String>>(param: &F) -> Result<QueueQuery, QueueError>
+    {
+        let sort = match param("sort").as_deref()
+        {
+            None | Some("name") => Sort::Name,
+            Some("depth")       => Sort::Depth,
+            Some(other)         => return Err(QueueError::Invalid(format!("\"sort\" must be name or depth, got \"{}\"", other))),
+        };
+        let descending = match param("order").as_deref()
+        {
+            None                => sort == Sort::Depth,
+            Some("asc")         => false,
+            Some("desc")        => true,
+            Some(other)         => return Err(QueueError::Invalid(format!("\"order\" must be asc or desc, got \"{}\"", other))),
+        };
+
+        Ok(QueueQuery
+        {
+            prefix:     param("prefix"),
+            sort,
+            descending,
+            offset:     match param("cursor") { Some(v) => v.parse::<usize>().map_err(|_| QueueError::Invalid(format!("\"cursor\" \"{}\" is not valid", v)))?, None => 0 },
+            limit:      audit::parse_limit(param("limit"))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MessageQuery
+{
+    pub after:        Option<u64>,
+    pub sender:       Option<String>,
+    pub priority:     Option<usize>,
+    pub min_priority: Option<usize>,
+    pub active:       Option<bool>,
+    pub since:        Option<SystemTime>,
+    pub until:        Option<SystemTime>,
+    pub limit:        usize,
+}
+
+fn _number<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<Option<T>, QueueError>
+{
+    match value
+    {
+        Some(v) => v.parse::<T>().map(Some).map_err(|_| QueueError::Invalid(format!("\"{}\" \"{}\" is not valid", name, v))),
+        None    => Ok(None),
+    }
+}
+
+impl MessageQuery
+{
+    /// Query string parameters `parse` reads, besides the queue `name`.
+    pub const PARAMS: &'static [&'static str] = &["name", "cursor", "sender", "priority", "min_priority", "active", "since", "until", "limit"];
+
+    /// `active` means not yet expired; `sender` matches the principal or `host:port`.
+    pub fn parse<F: Fn(&str) -> Option<String>>(param: &F) -> Result<MessageQuery, QueueError>
+    {
+        Ok(MessageQuery
+        {
+            after:        _number("cursor", param("cursor"))?,
+            sender:       param("sender"),
+            priority:     _number("priority", param("priority"))?,
+            min_priority: _number("min_priority", param("min_priority"))?,
+            active:       _number("active", param("active"))?,
+            since:        match param("since") { Some(v) => Some(audit::parse_time("since", &v)?), None => None },
+            until:        match param("until") { Some(v) => Some(audit::parse_time("until", &v)?), None => None },
+            limit:        audit::parse_limit(param("limit"))?,
+        })
+    }
+
+    fn matches(&self, message: &MSG, now: SystemTime) -> bool
+    {
+        let active = message.active && message.lifetime > now;
+
+        self.after.is_none_or(|id|          message.id > id)
+        && self.sender.as_ref().is_none_or(|s| message.sender.principal() == *s || message.sender.to_string() == *s)
+        && self.priority.is_none_or(|p|     message.priority == p)
+        && self.min_priority.is_none_or(|p| message.priority >= p)
+        && self.active.is_none_or(|a|       active == a)
+        && self.since.is_none_or(|t|        message.created >= t)
+        && self.until.is_none_or(|t|        message.created <= t)
+    }
+}
+
+fn _summary(queue: &Queue, now: SystemTime) -> json::JsonValue
+{
+    json::object!
+    {
+        "name"        => queue.name.clone(),
+        "depth"       => queue.depth(now),
+        "held"        => queue.data.len(),
+        "publishers"  => queue.publishers.len(),
+        "subscribers" => queue.subscribers.len(),
+        "bytes"       => queue.bytes(),
+    }
+}
+
+pub fn queues(client: &Client, filter: &QueueQuery) -> json::JsonValue
+{
+    let now      = SystemTime::now();
+    let mut rows = qgatawey::queues().values().filter_map(|q|
+    {
+        let queue = q.lock().unwrap();
+        let wants = filter.prefix.as_ref().is_none_or(|prefix| queue.name.starts_with(prefix.as_str()));
+
+        if !wants || !queue.acl.allows(client, acl::Permission::ReadMetadata) { return None; }
+
+        Some((queue.depth(now), _summary(&queue, now), queue.name.clone()))
+    }).collect::<Vec<(usize, json::JsonValue, String)>>();
+
+    match filter.sort
+    {
+        Sort::Name  => rows.sort_by(|a, b| a.2.cmp(&b.2)),
+        Sort::Depth => rows.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.2.cmp(&b.2))),
+    }
+
+    if filter.descending { rows.reverse(); }
+
+    let total = rows.len();
+    let end   = filter.offset.saturating_add(filter.limit);
+    let next  = if end < total { Some(end) } else { None };
+
+    json::object!
+    {
+        "queues" => rows.into_iter().skip(filter.offset).take(filter.limit).map(|row| row.1).collect::<Vec<json::JsonValue>>(),
+        "total"  => total,
+        "next"   => next,
+    }
+}
+
+pub fn info(client: &Client, queue: &Queue) -> Result<json::JsonValue, QueueError>
+{
+    queue.acl.check(client, acl::Permission::ReadMetadata, &queue.name)?;
+
+    let now      = SystemTime::now();
+    let mut info = _summary(queue, now);
+    let oldest   = queue.data.first().map(|message| message.id);
+    let newest   = queue.data.last().map(|message| message.id);
+
+    info["publishers"]  = queue.publishers.clone().into();
+    info["subscribers"] = queue.subscribers.clone().into();
+    info["oldest_id"]   = oldest.into();
+    info["newest_id"]   = newest.into();
+    info["acl"]         = queue.acl.clone().into();
+
+    Ok(info)
+}
+
+/// Ids only grow, so the last id on a page is a stable cursor even while messages are pushed.
+/// A subscriber sees the messages still waiting for it, so its selector applies. Any other caller
+/// sees every message if it may manage the queue, and none otherwise.
+pub fn messages(client: &Client, queue: &Queue, filter: &MessageQuery) -> Result<json::JsonValue, QueueError>
+{
+    queue.acl.check(client, acl::Permission::Subscribe, &queue.name)?;
+
+    let now  = SystemTime::now();
+    let all  = !queue.subscribers.contains(client) && queue.acl.allows(client, acl::Permission::Manage);
+    let page = queue.data.iter()
+        .filter(|message| filter.matches(message, now) && (all || message.recipients.contains(client)))
+        .take(filter.limit + 1).collect::<Vec<&MSG>>();
+    let next = if page.len() > filter.limit { Some(page[filter.limit - 1].id) } else { None };
+    let page = page.into_iter().take(filter.limit).map(MSG::loaded).collect::<Result<Vec<MSG>, QueueError>>()?;
+
+    Ok(json::object!
+    {
+        "queue"    => queue.name.clone(),
+        "messages" => page,
+        "next"     => next,
+    })
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::testing;
+
+    fn _params<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a
+    {
+        move |name| pairs.iter().find(|(key, _)| *key == name).map(|(_, value)| String::from(*value))
+    }
+
+    /// A queue created by `127.0.0.1:1` holding messages of priority 0, 5 and 9.
+    fn _queue() -> (Queue, Client)
+    {
+        let creator   = testing::client(1);
+        let mut queue = Queue::new(String::from("listing"), creator.clone());
+
+        for priority in [0, 5, 9]
+        {
+            queue.push(Payload::Plain(format!("p{}", priority)), creator.clone(), None, Some(priority), Properties::default()).unwrap();
+        }
+
+        (queue, creator)
+    }
+
+    fn _priorities(page: &json::JsonValue) -> Vec<usize>
+    {
+        page["messages"].members().map(|message| message["priority"].as_usize().unwrap()).collect()
+    }
+
+    #[test]
+    fn queue_queries_default_to_names_ascending()
+    {
+        let by_name  = QueueQuery::parse(&_params(&[])).unwrap();
+        let by_depth = QueueQuery::parse(&_params(&[("sort", "depth")])).unwrap();
+
+        assert_eq!((by_name.sort, by_name.descending, by_name.offset, by_name.limit), (Sort::Name, false, 0, audit::DEFAULT_LIMIT));
+        assert_eq!((by_depth.sort, by_depth.descending), (Sort::Depth, true));
+        assert!(matches!(QueueQuery::parse(&_params(&[("sort", "age")])),   Err(QueueError::Invalid(_))));
+        assert!(matches!(QueueQuery::parse(&_params(&[("order", "up")])),   Err(QueueError::Invalid(_))));
+        assert!(matches!(QueueQuery::parse(&_params(&[("cursor", "-1")])),  Err(QueueError::Invalid(_))));
+    }
+
+    #[test]
+    fn message_queries_check_their_values()
+    {
+        let query = MessageQuery::parse(&_params(&[("min_priority", "5"), ("active", "true"), ("since", "0")])).unwrap();
+
+        assert_eq!((query.min_priority, query.active, query.since), (Some(5), Some(true), Some(std::time::UNIX_EPOCH)));
+        assert!(matches!(MessageQuery::parse(&_params(&[("priority", "high")])), Err(QueueError::Invalid(_))));
+        assert!(matches!(MessageQuery::parse(&_params(&[("active", "yes")])),    Err(QueueError::Invalid(_))));
+        assert!(matches!(MessageQuery::parse(&_params(&[("limit", "501")])),     Err(QueueError::Invalid(_))));
+    }
+
+    #[test]
+    fn pages_follow_the_cursor()
+    {
+        let (queue, creator) = _queue();
+        let first            = messages(&creator, &queue, &MessageQuery { limit: 2, ..MessageQuery::default() }).unwrap();
+
+        assert_eq!(_priorities(&first), vec![0, 5]);
+
+        let after  = first["next"].as_u64();
+        let second = messages(&creator, &queue, &MessageQuery { after, limit: 2, ..MessageQuery::default() }).unwrap();
+
+        assert_eq!(_priorities(&second), vec![9]);
+        assert!(second["next"].is_null());
+    }
+
+    #[test]
+    fn filters_apply()
+    {
+        let (queue, creator) = _queue();
+        let page             = |filter: MessageQuery| _priorities(&messages(&creator, &queue, &MessageQuery { limit: 10, ..filter }).unwrap());
+
+        assert_eq!(page(MessageQuery { min_priority: Some(5), ..MessageQuery::default() }), vec![5, 9]);
+        assert_eq!(page(MessageQuery { priority: Some(0), ..MessageQuery::default() }), vec![0]);
+        assert_eq!(page(MessageQuery { sender: Some(String::from("127.0.0.1:2")), ..MessageQuery::default() }), Vec::<usize>::new());
+        assert_eq!(page(MessageQuery { active: Some(false), ..MessageQuery::default() }), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn callers_see_only_what_waits_for_them()
+    {
+        let (mut queue, creator) = _queue();
+        let (picky, stranger)    = (testing::client(2), testing::client(3));
+        let everything           = MessageQuery { limit: 10, ..MessageQuery::default() };
+
+        queue.sub_with(picky.clone(), Some("priority > 4".parse().unwrap())).unwrap();
+        queue.push(Payload::Plain(String::from("p7")), creator.clone(), None, Some(7), Properties::default()).unwrap();
+
+        assert_eq!(_priorities(&messages(&picky,    &queue, &everything).unwrap()), vec![7]);
+        assert_eq!(_priorities(&messages(&stranger, &queue, &everything).unwrap()), Vec::<usize>::new());
+        assert_eq!(_priorities(&messages(&creator,  &queue, &everything).unwrap()), vec![0, 5, 9, 7]);
+
+        queue.sub_with(creator.clone(), Some("priority = 0".parse().unwrap())).unwrap();
+
+        assert_eq!(_priorities(&messages(&creator,  &queue, &everything).unwrap()), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn listing_needs_permission()
+    {
+        let (mut queue, creator) = _queue();
+        let other                = testing::client(2);
+
+        queue.revoke(&creator, String::from(acl::ANYONE), acl::Permission::Subscribe).unwrap();
+
+        assert!(matches!(messages(&other, &queue, &MessageQuery { limit: 10, ..MessageQuery::default() }), Err(QueueError::Forbidden(_))));
+        assert_eq!(info(&other, &queue).unwrap()["held"], 3);
+
+        queue.revoke(&creator, String::from(acl::ANYONE), acl::Permission::ReadMetadata).unwrap();
+
+        assert!(matches!(info(&other, &queue), Err(QueueError::Forbidden(_))));
+    }
+}
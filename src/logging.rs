@@ -0,0 +1,147 @@
+use super::*;
+use std::fs::{self, File, OpenOptions};
+use chrono::SecondsFormat;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level
+{
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl std::str::FromStr for Level
+{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>
+    {
+        match name
+        {
+            "debug" => Ok(Level::Debug),
+            "info"  => Ok(Level::Info),
+            "warn"  => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _       => Err(format!("\"{}\" is not a log level (debug, info, warn, error)", name)),
+        }
+    }
+}
+
+impl fmt::Display for Level
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            Level::Debug => "debug",
+            Level::Info  => "info",
+            Level::Warn  => "warn",
+            Level::Error => "error",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Where log lines go: stderr, or a file rotated to `<file>.1` .. `<file>.<keep>` past `max_bytes`.
+struct Sink
+{
+    settings: config::LogSettings,
+    file:     Option<File>,
+    written:  u64,
+}
+
+impl Sink
+{
+    fn open(settings: config::LogSettings) -> Result<Sink, String>
+    {
+        let mut this = Sink { settings, file: None, written: 0 };
+
+        if let Some(path) = this.settings.file.clone()
+        {
+            let file = OpenOptions::new().create(true).append(true).open(&path).map_err(|e| format!("\"{}\": {}", path, e))?;
+
+            this.written = file.metadata().map(|m| m.len()).unwrap_or(0);
+            this.file    = Some(file);
+        }
+
+        Ok(this)
+    }
+
+    fn _rotate(&mut self, path: &str) -> std::io::Result<()>
+    {
+        self.file = None;
+
+        for index in (1..self.settings.keep).rev()
+        {
+            let from = format!("{}.{}", path, index);
+
+            if std::path::Path::new(&from).exists() { fs::rename(&from, format!("{}.{}", path, index + 1))?; }
+        }
+
+        if self.settings.keep > 0 { fs::rename(path, format!("{}.1", path))?; } else { fs::remove_file(path)?; }
+
+        self.file    = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        self.written = 0;
+
+        Ok(())
+    }
+
+    fn write(&mut self, line: &str)
+    {
+        let path = match self.settings.file.clone()
+        {
+            Some(path) => path,
+            None       => { eprintln!("{}", line); return; },
+        };
+
+        if self.written > 0 && self.written + line.len() as u64 + 1 > self.settings.max_bytes
+        {
+            if let Err(e) = self._rotate(&path) { eprintln!("miniq: cannot rotate \"{}\": {}", path, e); }
+        }
+
+        if let Some(file) = self.file.as_mut()
+        {
+            if writeln!(file, "{}", line).is_ok() { self.written += line.len() as u64 + 1; }
+        }
+    }
+}
+
+lazy_static!
+{
+    static ref SINK: Mutex<Option<Sink>> = Mutex::new(None);
+}
+
+/// Starts logging with `settings`; until then events go to stderr at `info`.
+pub fn init(settings: &config::LogSettings) -> Result<(), String>
+{
+    *SINK.lock().unwrap() = Some(Sink::open(settings.clone())?);
+
+    Ok(())
+}
+
+/// Writes one JSON line: `ts`, `level` and `event`, followed by `fields`.
+pub fn event(level: Level, event: &str, fields: json::JsonValue)
+{
+    let mut sink = SINK.lock().unwrap();
+    let min      = sink.as_ref().map(|s| s.settings.level).unwrap_or(Level::Info);
+
+    if level < min { return; }
+
+    let mut line = json::object!
+    {
+        "ts"    => Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level" => level.to_string(),
+        "event" => event,
+    };
+
+    for (key, value) in fields.entries() { line[key] = value.clone(); }
+
+    match sink.as_mut()
+    {
+        Some(sink) => sink.write(&line.dump()),
+        None       => eprintln!("{}", line.dump()),
+    }
+}
@@ -0,0 +1,309 @@
+//! Memory accounting and flow control.
+//!
+//! Every message is charged its approximate footprint: the payload bytes held in memory, its
+//! properties and a fixed overhead; `Queue::bytes` sums a queue. Each `Broker` keeps its own
+//! total, recounted by every `Broker::expire` pass and raised by each publish in between, so it
+//! follows growth at once and releases (consumed and expired messages, purges, deleted queues)
+//! within a second.
+//!
+//! Once the total reaches `memory.limit`, `memory.flow_control` decides what publishers meet:
+//! `reject` fails them with `429 memory_full`, `block` holds them up to `memory.block_timeout`
+//! seconds for room before failing the same way, and `page` keeps accepting them but stores new
+//! payloads on disk and moves the oldest payloads held in memory there until the total is under
+//! the limit again.
+
+use super::*;
+
+use std::sync::Condvar;
+use config::FlowControl;
+
+/// Bytes held by one broker's queues, and the condition its blocked publishers wait on for room.
+#[derive(Debug, Default)]
+pub struct Memory
+{
+    bytes: Mutex<Bytes>,
+    room:  Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Bytes
+{
+    /// Charged for messages the queues hold.
+    held:     usize,
+    /// Let through by `admit` for publishes not done yet.
+    reserved: usize,
+}
+
+impl Memory
+{
+    /// Approximate bytes all queues hold, counting the publishes under way.
+    pub fn used(&self) -> usize
+    {
+        let bytes = self.bytes.lock().unwrap();
+
+        bytes.held + bytes.reserved
+    }
+
+    /// Whether new payloads go to disk: `page` flow control with the limit reached.
+    pub fn pages(&self, settings: &config::Settings) -> bool
+    {
+        settings.memory.flow_control == FlowControl::Page && settings.memory.limit.is_some_and(|limit| self.used() >= limit)
+    }
+
+    /// Counts messages just published.
+    pub fn charge(&self, bytes: usize)
+    {
+        self.bytes.lock().unwrap().held += bytes;
+    }
+
+    /// Gives back what `admit` reserved, once the publish is charged or failed.
+    pub fn release(&self, reserved: usize)
+    {
+        let mut bytes = self.bytes.lock().unwrap();
+
+        bytes.reserved = bytes.reserved.saturating_sub(reserved);
+        self.room.notify_all();
+    }
+
+    /// Takes `freed` bytes no longer held in memory off the total.
+    pub fn free(&self, freed: usize)
+    {
+        let mut bytes = self.bytes.lock().unwrap();
+
+        bytes.held = bytes.held.saturating_sub(freed);
+        self.room.notify_all();
+    }
+
+    /// Sets the bytes held to `bytes`, as recounted over every queue, and wakes blocked publishers.
+    pub fn settle(&self, bytes: usize)
+    {
+        self.bytes.lock().unwrap().held = bytes;
+        self.room.notify_all();
+    }
+
+    /// Lets through, or fails, the publish of payloads `length` bytes long, as `memory.flow_control`
+    /// says. What it lets through is reserved in the same lock, so publishers racing for the last
+    /// room cannot all get it; the bytes reserved are returned for `release`.
+    pub fn admit(&self, length: usize) -> Result<usize, QueueError>
+    {
+        self._admit(length, &config::settings())
+    }
+
+    fn _admit(&self, length: usize, settings: &config::Settings) -> Result<usize, QueueError>
+    {
+        let limit     = match settings.memory.limit
+        {
+            Some(limit) if settings.memory.flow_control != FlowControl::Page => limit,
+            _                                                                => return Ok(0),
+        };
+        let spilled   = settings.messages.spill_above.is_some_and(|above| length > above);
+        let needed    = std::mem::size_of::<MSG>() + if spilled { 0 } else { length };
+        let mut bytes = self.bytes.lock().unwrap();
+
+        if settings.memory.flow_control == FlowControl::Block
+        {
+            bytes = self.room.wait_timeout_while(bytes, Duration::from_secs_f64(settings.memory.block_timeout), |bytes| bytes.held + bytes.reserved + needed > limit).unwrap().0;
+        }
+
+        let used = bytes.held + bytes.reserved;
+
+        if used + needed > limit
+        {
+            logging::event(logging::Level::Debug, "publish refused", json::object!{"used" => used, "limit" => limit, "bytes" => needed});
+
+            return Err(QueueError::MemoryFull(format!("broker memory is full ({} of {} bytes)", used, limit)));
+        }
+
+        bytes.reserved += needed;
+
+        Ok(needed)
+    }
+}
+
+/// Under `page` flow control past the limit, moves the oldest payloads held in memory, across
+/// every queue, to files until the total is back under it; returns how many bytes that freed.
+pub fn page(broker: &Broker) -> usize
+{
+    let settings = config::settings();
+    let limit    = match settings.memory.limit
+    {
+        Some(limit) if settings.memory.flow_control == FlowControl::Page => limit,
+        _                                                                => return 0,
+    };
+    let excess   = broker.memory.used().saturating_sub(limit);
+
+    if excess == 0 { return 0; }
+
+    let handles  = broker.queues().values().cloned().collect::<Vec<Arc<Mutex<Queue>>>>();
+    let mut cold = Vec::new();
+
+    for (index, q) in handles.iter().enumerate()
+    {
+        let queue = q.lock().unwrap();
+
+        cold.extend(queue.data.iter().filter(|message| message.data.stored_len() > 0).map(|message| (message.created, index, message.id, message.data.stored_len())));
+    }
+
+    cold.sort();
+
+    let mut chosen: HashMap<usize, Vec<u64>> = HashMap::new();
+    let mut total  = 0;
+
+    for (_, index, id, bytes) in cold
+    {
+        if total >= excess { break; }
+
+        chosen.entry(index).or_default().push(id);
+        total += bytes;
+    }
+
+    let dir   = blob::dir(&settings);
+    let freed = chosen.iter().map(|(index, ids)| handles[*index].lock().unwrap().page_out(ids, &dir)).sum::<usize>();
+
+    broker.memory.free(freed);
+    logging::event(logging::Level::Info, "payloads paged out", json::object!{"messages" => chosen.values().map(Vec::len).sum::<usize>(), "bytes" => freed});
+
+    freed
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::testing;
+
+    /// Room for exactly `messages` empty payloads.
+    fn _settings(flow_control: FlowControl, messages: usize) -> config::Settings
+    {
+        let memory = config::MemorySettings { limit: Some(messages * std::mem::size_of::<MSG>()), flow_control, block_timeout: 5.0 };
+
+        config::Settings { memory, ..config::Settings::default() }
+    }
+
+    #[test]
+    fn admit_reserves_the_room_it_grants()
+    {
+        let memory   = Memory::default();
+        let settings = _settings(FlowControl::Reject, 2);
+        let first    = memory._admit(0, &settings).unwrap();
+        let second   = memory._admit(0, &settings).unwrap();
+
+        assert!(matches!(memory._admit(0, &settings), Err(QueueError::MemoryFull(_))));
+        assert_eq!(memory.used(), first + second);
+
+        memory.release(first);
+
+        assert!(memory._admit(0, &settings).is_ok());
+    }
+
+    #[test]
+    fn page_mode_and_no_limit_reserve_nothing()
+    {
+        let memory = Memory::default();
+
+        assert_eq!(memory._admit(1000, &config::Settings::default()), Ok(0));
+        assert_eq!(memory._admit(1000, &_settings(FlowControl::Page, 0)), Ok(0));
+        assert_eq!(memory.used(), 0);
+    }
+
+    #[test]
+    fn blocked_publishers_wait_for_room()
+    {
+        let memory   = Arc::new(Memory::default());
+        let settings = _settings(FlowControl::Block, 1);
+        let held     = memory._admit(0, &settings).unwrap();
+        let waiting  = memory.clone();
+        let waiter   = thread::spawn(move || waiting._admit(0, &settings));
+
+        thread::sleep(Duration::from_millis(50));
+        memory.release(held);
+
+        assert_eq!(waiter.join().unwrap(), Ok(held));
+    }
+
+    #[test]
+    fn consumed_and_expired_messages_are_freed()
+    {
+        let (broker, client) = testing::broker(&["jobs"]);
+
+        broker.publish(&client, "jobs", String::from("taken"), None, None).unwrap();
+        broker.publish(&client, "jobs", String::from("expired"), Some(0.001), None).unwrap();
+
+        assert!(broker.memory.used() > 0);
+
+        thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(broker.consume(&client, "jobs").unwrap().map(|message| message.data.to_string()), Some(String::from("taken")));
+        assert_eq!(broker.expire(), 1);
+        assert!(broker.update("jobs", |queue| Ok(queue.data.is_empty())).unwrap());
+        assert_eq!(broker.memory.used(), 0);
+    }
+
+    #[test]
+    fn publishes_charge_their_broker_only()
+    {
+        let (broker, client) = testing::broker(&["jobs"]);
+        let (other, _)       = testing::broker(&["jobs"]);
+        let message          = broker.publish(&client, "jobs", String::from("data"), None, None).unwrap();
+
+        assert_eq!(broker.memory.used(), message.footprint());
+        assert_eq!(other.memory.used(), 0);
+
+        broker.expire();
+
+        assert_eq!(broker.memory.used(), message.footprint());
+    }
+
+    #[test]
+    fn replies_taken_by_a_request_are_freed()
+    {
+        let (broker, client) = testing::broker(&["jobs"]);
+        let replier          = |broker: &Broker|
+        {
+            loop
+            {
+                if let Some(request) = broker.update("jobs", |queue| Ok(queue.data.last().cloned())).unwrap()
+                {
+                    let properties = Properties { correlation_id: request.properties.correlation_id.clone(), ..Properties::default() };
+
+                    broker.publish_with(&client, request.properties.reply_to.as_ref().unwrap(), String::from("pong"), None, None, properties).unwrap();
+                    broker.publish_with(&client, request.properties.reply_to.as_ref().unwrap(), String::from("late"), None, None, Properties::default()).unwrap();
+                    break;
+                }
+
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        let reply = thread::scope(|scope|
+        {
+            scope.spawn(|| replier(&broker));
+            broker.request(&client, "jobs", String::from("ping"), None, None, Some(5.0)).unwrap()
+        });
+
+        let held = broker.update("jobs", |queue| Ok(queue.bytes())).unwrap();
+
+        assert_eq!(reply.data.to_string(), "pong");
+        assert_eq!(broker.memory.used(), held);
+
+        broker.expire();
+
+        assert_eq!(broker.memory.used(), held);
+    }
+
+    #[test]
+    fn duplicates_are_not_charged()
+    {
+        let (broker, client) = testing::broker(&["jobs"]);
+        let properties       = Properties { dedup_id: Some(String::from("once")), ..Properties::default() };
+
+        broker.publish_with(&client, "jobs", String::from("data"), None, None, properties.clone()).unwrap();
+
+        let used = broker.memory.used();
+
+        let _ = broker.publish_with(&client, "jobs", String::from("data"), None, None, properties);
+
+        assert_eq!(broker.memory.used(), used);
+    }
+}
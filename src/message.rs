@@ -0,0 +1,225 @@
+use super::*;
+
+pub use miniq_model::Properties;
+
+
+/// A message body as held in memory: the published text, that text compressed once it is longer
+/// than `compression.threshold`, or a file holding it once it is longer than `messages.spill_above`.
+/// It is written out, and compared, as the text.
+#[derive(Debug, Clone)]
+pub enum Payload
+{
+    Plain(String),
+    Packed(compression::Codec, Vec<u8>),
+    Spilled(Arc<blob::Blob>),
+}
+
+
+impl Payload
+{
+    /// Stores `text` as `settings` say, spilled too while `paging` (see `memory::Memory::pages`);
+    /// kept in memory if it cannot be spilled, and plain if compressing would not make it smaller.
+    pub fn pack(text: String, settings: &config::Settings, paging: bool) -> Payload
+    {
+        if settings.messages.spill_above.is_some_and(|limit| text.len() > limit) || paging
+        {
+            match blob::Blob::write(&blob::dir(settings), &text)
+            {
+                Ok(blob) => return Payload::Spilled(Arc::new(blob)),
+                Err(err) => logging::event(logging::Level::Warn, "payload not spilled", json::object!{"bytes" => text.len(), "error" => err.to_string()}),
+            }
+        }
+
+        let codec = settings.compression.codec;
+
+        if codec == compression::Codec::None || text.len() <= settings.compression.threshold
+        {
+            return Payload::Plain(text);
+        }
+
+        match codec.compress(text.as_bytes())
+        {
+            Ok(bytes) if bytes.len() < text.len() => Payload::Packed(codec, bytes),
+            _                                     => Payload::Plain(text),
+        }
+    }
+
+    /// The published text; `Internal` if it cannot be unpacked or its file read.
+    pub fn text(&self) -> Result<String, QueueError>
+    {
+        match self
+        {
+            Payload::Plain(text)          => Ok(text.clone()),
+            Payload::Packed(codec, bytes) => codec.decompress(bytes)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| String::from_utf8(bytes).map_err(|err| err.to_string()))
+                .map_err(|err| QueueError::Internal(format!("payload cannot be unpacked: {}", err))),
+            Payload::Spilled(blob)        => blob.read().map_err(|err|
+            {
+                logging::event(logging::Level::Error, "blob unreadable", json::object!{"path" => blob.path().display().to_string(), "error" => err.to_string()});
+                QueueError::Internal(format!("payload cannot be read: {}", err))
+            }),
+        }
+    }
+
+    /// At most `chars` characters of the text; a spilled payload is only read that far.
+    pub fn preview(&self, chars: usize) -> Result<String, QueueError>
+    {
+        match self
+        {
+            Payload::Plain(text)   => Ok(text.chars().take(chars).collect()),
+            Payload::Packed(..)    => Ok(self.text()?.chars().take(chars).collect()),
+            Payload::Spilled(blob) => blob.prefix(chars.saturating_mul(4))
+                .map(|text| text.chars().take(chars).collect())
+                .map_err(|err| QueueError::Internal(format!("payload cannot be read: {}", err))),
+        }
+    }
+
+    /// Bytes it takes in memory; a spilled payload takes none.
+    pub fn stored_len(&self) -> usize
+    {
+        match self
+        {
+            Payload::Plain(text)      => text.len(),
+            Payload::Packed(_, bytes) => bytes.len(),
+            Payload::Spilled(_)       => 0,
+        }
+    }
+}
+
+
+impl std::convert::From<String> for Payload
+{
+    fn from(text: String) -> Self
+    {
+        Payload::pack(text, &config::settings(), false)
+    }
+}
+
+
+impl PartialEq for Payload
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        match (self, other)
+        {
+            (Payload::Plain(a), Payload::Plain(b)) => a == b,
+            _                                      => matches!((self.text(), other.text()), (Ok(a), Ok(b)) if a == b),
+        }
+    }
+}
+
+
+/// Fails when the payload cannot be read; see `text`.
+impl fmt::Display for Payload
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Payload::Plain(text) => write!(f, "{}", text),
+            packed               => write!(f, "{}", packed.text().map_err(|_| fmt::Error)?),
+        }
+    }
+}
+
+
+impl serde::Serialize for Payload
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        match self
+        {
+            Payload::Plain(text) => serializer.serialize_str(text),
+            packed               => serializer.serialize_str(&packed.text().map_err(serde::ser::Error::custom)?),
+        }
+    }
+}
+
+
+impl JsonSchema for Payload
+{
+    fn schema_name() -> String
+    {
+        String::from("Payload")
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema
+    {
+        String::json_schema(gen)
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct MSG
+{
+    pub id:         u64,
+    pub sender:     Client,
+    pub recipients: Vec<Client>,
+    #[serde(serialize_with = "model::time::serialize")]
+    #[schemars(with = "String")]
+    pub created:    SystemTime,
+    /// When the message expires.
+    #[serde(serialize_with = "model::time::serialize")]
+    #[schemars(with = "String")]
+    pub lifetime:   SystemTime,
+    pub data:       Payload,
+    pub active:     bool,
+    pub priority:   usize,
+    #[serde(flatten)]
+    pub properties: Properties,
+}
+
+
+impl MSG
+{
+    pub fn new(id: u64, data: Payload, sender: Client, recipients: Vec<Client>, lifetime: SystemTime, priority: Option<usize>) -> MSG
+    {
+        MSG
+        {
+            id,
+            sender,
+            recipients,
+            created:    SystemTime::now(),
+            lifetime,
+            data,
+            active:     true,
+            priority:   priority.unwrap_or(0),
+            properties: Properties::default(),
+        }
+    }
+
+    /// A copy holding its payload as plain text, so that writing it out cannot fail; `Internal` if
+    /// the payload cannot be read. Every answer carrying a message is built from one.
+    pub fn loaded(&self) -> Result<MSG, QueueError>
+    {
+        Ok(MSG { data: Payload::Plain(self.data.text()?), ..self.clone() })
+    }
+
+    /// A copy with an empty payload, for remembering a message after it has left its queue.
+    pub fn without_data(&self) -> MSG
+    {
+        MSG { data: Payload::Plain(String::new()), ..self.clone() }
+    }
+
+    /// Approximate bytes it takes in memory; see `memory`.
+    pub fn footprint(&self) -> usize
+    {
+        let properties = &self.properties;
+        let strings    = [&properties.reply_to, &properties.correlation_id, &properties.dedup_id, &properties.group_id].iter().filter_map(|value| value.as_ref()).map(String::len).sum::<usize>()
+            + properties.headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>();
+
+        std::mem::size_of::<MSG>() + self.data.stored_len() + self.recipients.len() * std::mem::size_of::<Client>() + strings
+    }
+}
+
+
+
+impl std::convert::From<MSG> for json::JsonValue
+{
+    fn from(message: MSG) -> Self
+    {
+        model::to_json(&message)
+    }
+}
@@ -0,0 +1,298 @@
+use super::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+const LATENCY_BUCKETS:   [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const LOCK_WAIT_BUCKETS: [f64; 7]  = [0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0, 10.0];
+
+/// Queue name, depth, subscribers, publishers and bytes, as sampled for the gauges.
+type Gauge = (String, usize, usize, usize, usize);
+/// Metric name, help text and how to read the value from a sample.
+type Column<T, V> = (&'static str, &'static str, fn(&T) -> V);
+
+#[derive(Debug, Clone)]
+struct Histogram
+{
+    bounds: Vec<f64>,
+    counts: Vec<u64>,
+    sum:    f64,
+    count:  u64,
+}
+
+impl Histogram
+{
+    fn new(bounds: &[f64]) -> Histogram
+    {
+        Histogram { bounds: bounds.to_vec(), counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, seconds: f64)
+    {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut())
+        {
+            if seconds <= *bound { *count += 1; }
+        }
+
+        self.sum   += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str)
+    {
+        let sep = if labels.is_empty() { "" } else { "," };
+
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter())
+        {
+            out.push_str(&format!("{}_bucket{{{}{}le=\"{}\"}} {}\n", name, labels, sep, bound, count));
+        }
+
+        let braced = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+
+        out.push_str(&format!("{}_bucket{{{}{}le=\"+Inf\"}} {}\n", name, labels, sep, self.count));
+        out.push_str(&format!("{}_sum{} {}\n",   name, braced, self.sum));
+        out.push_str(&format!("{}_count{} {}\n", name, braced, self.count));
+    }
+}
+
+/// Running totals per queue; Prometheus derives rates from them.
+#[derive(Debug, Clone, Default)]
+pub struct QueueCounters
+{
+    pub published:     u64,
+    pub consumed:      u64,
+    pub acked:         u64,
+    pub expired:       u64,
+    /// Stays zero until queues get a dead-letter queue; exported so dashboards need no change then.
+    pub dead_lettered: u64,
+    pub deduplicated:  u64,
+    /// Publishes in each of the last 60 seconds, as `(unix second, count)`; not exported, Prometheus
+    /// derives rates from `published`, but the console shows one.
+    recent:            VecDeque<(u64, u64)>,
+}
+
+impl QueueCounters
+{
+    pub fn publish(&mut self, now: SystemTime)
+    {
+        let second = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        self.published += 1;
+
+        match self.recent.back_mut()
+        {
+            Some((last, count)) if *last == second => *count += 1,
+            _                                      => self.recent.push_back((second, 1)),
+        }
+
+        while self.recent.front().is_some_and(|(first, _)| first + 60 <= second) { self.recent.pop_front(); }
+    }
+
+    /// Publishes in the minute up to `now`.
+    pub fn published_last_minute(&self, now: SystemTime) -> u64
+    {
+        let second = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        self.recent.iter().filter(|(at, _)| at + 60 > second).map(|(_, count)| count).sum()
+    }
+}
+
+struct Registry
+{
+    routes: HashMap<String, Histogram>,
+    queues: HashMap<String, QueueCounters>,
+}
+
+/// The queue table lock-wait histogram; it is observed on every `Broker::queues` call, so it is
+/// kept in atomics rather than behind the registry lock.
+struct LockWait
+{
+    counts:    [AtomicU64; LOCK_WAIT_BUCKETS.len()],
+    count:     AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+static LOCK_WAIT: LockWait = LockWait
+{
+    counts:    [const { AtomicU64::new(0) }; LOCK_WAIT_BUCKETS.len()],
+    count:     AtomicU64::new(0),
+    sum_nanos: AtomicU64::new(0),
+};
+
+impl LockWait
+{
+    fn snapshot(&self) -> Histogram
+    {
+        Histogram
+        {
+            bounds: LOCK_WAIT_BUCKETS.to_vec(),
+            counts: self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect(),
+            sum:    self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+            count:  self.count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+lazy_static!
+{
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry
+    {
+        routes: HashMap::new(),
+        queues: HashMap::new(),
+    });
+}
+
+pub fn observe_request(route: &str, elapsed: Duration)
+{
+    REGISTRY.lock().unwrap().routes.entry(String::from(route)).or_insert_with(|| Histogram::new(&LATENCY_BUCKETS)).observe(elapsed.as_secs_f64());
+}
+
+pub fn observe_lock_wait(elapsed: Duration)
+{
+    let seconds = elapsed.as_secs_f64();
+
+    for (bound, count) in LOCK_WAIT_BUCKETS.iter().zip(LOCK_WAIT.counts.iter())
+    {
+        if seconds <= *bound { count.fetch_add(1, Ordering::Relaxed); }
+    }
+
+    LOCK_WAIT.sum_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    LOCK_WAIT.count.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record<F: FnOnce(&mut QueueCounters)>(queue: &str, update: F)
+{
+    update(REGISTRY.lock().unwrap().queues.entry(String::from(queue)).or_default());
+}
+
+/// Publishes to `queue` in the minute up to `now`.
+pub fn published_last_minute(queue: &str, now: SystemTime) -> u64
+{
+    REGISTRY.lock().unwrap().queues.get(queue).map_or(0, |counters| counters.published_last_minute(now))
+}
+
+/// Drops the counters of a queue that was deleted or expired, so its series stop being exported.
+pub fn forget(queue: &str)
+{
+    REGISTRY.lock().unwrap().queues.remove(queue);
+}
+
+fn _label(value: &str) -> String
+{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn _header(out: &mut String, name: &str, kind: &str, help: &str)
+{
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+}
+
+/// Everything in the Prometheus text exposition format.
+pub fn render() -> String
+{
+    let now        = SystemTime::now();
+    let mut out    = String::new();
+    let mut gauges: Vec<Gauge> = Vec::new();
+
+    for (name, q) in qgatawey::queues().iter()
+    {
+        let queue = q.lock().unwrap();
+
+        gauges.push((name.clone(), queue.depth(now), queue.subscribers.len(), queue.publishers.len(), queue.bytes()));
+    }
+
+    gauges.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let registry = REGISTRY.lock().unwrap();
+    let gauge_columns: [Column<Gauge, usize>; 4] =
+    [
+        ("miniq_queue_depth",       "Active, unexpired messages held by the queue.", |g| g.1),
+        ("miniq_queue_subscribers", "Subscribers of the queue.",                     |g| g.2),
+        ("miniq_queue_publishers",  "Publishers of the queue.",                      |g| g.3),
+        ("miniq_queue_bytes",       "Approximate bytes the queue's messages take.",  |g| g.4),
+    ];
+
+    for (metric, help, column) in gauge_columns.iter()
+    {
+        _header(&mut out, metric, "gauge", help);
+
+        for g in &gauges { out.push_str(&format!("{}{{queue=\"{}\"}} {}\n", metric, _label(&g.0), column(g))); }
+    }
+
+    _header(&mut out, "miniq_memory_bytes", "gauge", "Approximate bytes all queues hold.");
+    out.push_str(&format!("miniq_memory_bytes {}\n", qgatawey::broker().memory.used()));
+
+    let mut names = registry.queues.keys().cloned().collect::<Vec<String>>();
+    let counter_columns: [Column<QueueCounters, u64>; 6] =
+    [
+        ("miniq_messages_published_total",     "Messages published to the queue.",       |c| c.published),
+        ("miniq_messages_consumed_total",      "Messages consumed from the queue.",      |c| c.consumed),
+        ("miniq_messages_acked_total",         "Messages acknowledged by consumers.",    |c| c.acked),
+        ("miniq_messages_expired_total",       "Messages that outlived their lifetime.", |c| c.expired),
+        ("miniq_messages_dead_lettered_total", "Messages moved to a dead-letter queue.", |c| c.dead_lettered),
+        ("miniq_messages_deduplicated_total",  "Publishes dropped as duplicates.",       |c| c.deduplicated),
+    ];
+
+    names.sort();
+
+    for (metric, help, column) in counter_columns.iter()
+    {
+        _header(&mut out, metric, "counter", help);
+
+        for name in &names { out.push_str(&format!("{}{{queue=\"{}\"}} {}\n", metric, _label(name), column(&registry.queues[name]))); }
+    }
+
+    let mut routes = registry.routes.keys().cloned().collect::<Vec<String>>();
+
+    routes.sort();
+    _header(&mut out, "miniq_request_duration_seconds", "histogram", "Time spent handling requests, by route.");
+
+    for route in &routes
+    {
+        registry.routes[route].render(&mut out, "miniq_request_duration_seconds", &format!("route=\"{}\"", _label(route)));
+    }
+
+    _header(&mut out, "miniq_queues_lock_wait_seconds", "histogram", "Time spent waiting for the queue table lock.");
+    LOCK_WAIT.snapshot().render(&mut out, "miniq_queues_lock_wait_seconds", "");
+
+    out
+}
+
+pub fn export() -> IronResult<Response>
+{
+    let mut response = Response::new();
+
+    response.set_mut(status::Ok);
+    response.headers.set_raw("Content-Type", vec![b"text/plain; version=0.0.4; charset=utf-8".to_vec()]);
+    response.set_mut(render());
+
+    Ok(response)
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn last_minute_counts_recent_publishes_only()
+    {
+        let start        = UNIX_EPOCH + Duration::from_secs(1000);
+        let mut counters = QueueCounters::default();
+
+        counters.publish(start);
+        counters.publish(start);
+        counters.publish(start + Duration::from_secs(30));
+        counters.publish(start + Duration::from_secs(59));
+
+        assert_eq!(counters.published_last_minute(start + Duration::from_secs(59)), 4);
+        assert_eq!(counters.published_last_minute(start + Duration::from_secs(60)), 2);
+
+        counters.publish(start + Duration::from_secs(120));
+
+        assert_eq!(counters.published, 5);
+        assert_eq!(counters.published_last_minute(start + Duration::from_secs(120)), 1);
+        assert_eq!(counters.recent.len(), 1);
+    }
+}
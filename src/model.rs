@@ -0,0 +1,330 @@
+//! Checking of the JSON route bodies, whose types `miniq-model` shares with the client.
+//!
+//! A request body is deserialized with its field paths tracked, so a value of the wrong type is
+//! reported against the field that carries it; `Body::check` then rejects values that parse but
+//! cannot be used. Either way the route answers `422 invalid` with a `fields` map.
+
+use super::*;
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+pub use miniq_model::{time, Ack, Call, Deleted, ErrorBody, Message, Publisher, Push, QueueName, ReplyQueue, Restored, Role, Staged, StagedAck, StagedPush, TransactionId, MAX_LIFETIME, TIME_FORMAT};
+
+/// Body of `/sub`, its selector parsed.
+pub type Subscribe = miniq_model::Subscribe<selector::Selector>;
+/// Body of `/grant` and `/revoke`, its permission parsed.
+pub type Grant     = miniq_model::Grant<acl::Permission>;
+
+/// Field paths with what is wrong with each, in the order they were found.
+pub type FieldErrors = Vec<(String, String)>;
+
+/// A route's request body.
+pub trait Body: DeserializeOwned + 'static
+{
+    /// Adds a `(field, problem)` pair for every value that parsed but is not acceptable.
+    fn check(&self, _errors: &mut FieldErrors) {}
+
+    /// Parses the `#[serde(flatten)]` parts of a body that failed to parse on their own: serde
+    /// reports an error inside a flattened part against the whole body, not the field holding it.
+    fn parts(_body: &serde_json::Value) -> Result<(), QueueError> { Ok(()) }
+}
+
+/// A JSON request body as `Server::_handler` hands it to the route, with the value of the route's
+/// documented body type when it has one, so that `parse` does not read the JSON twice.
+pub struct Formdata
+{
+    json:   serde_json::Value,
+    parsed: Option<Box<dyn std::any::Any>>,
+}
+
+impl Formdata
+{
+    pub fn new(json: serde_json::Value) -> Formdata
+    {
+        Formdata { json, parsed: None }
+    }
+
+    pub fn parsed(json: serde_json::Value, parsed: Box<dyn std::any::Any>) -> Formdata
+    {
+        Formdata { json, parsed: Some(parsed) }
+    }
+}
+
+/// The body as `T`: the value already parsed when it is one, `parse_json` of the JSON otherwise.
+pub fn parse<T: Body>(body: Formdata) -> Result<T, QueueError>
+{
+    match body.parsed.map(|parsed| parsed.downcast::<T>())
+    {
+        Some(Ok(value)) => Ok(*value),
+        _               => parse_json(&body.json),
+    }
+}
+
+/// Deserializes and checks a request body.
+pub fn parse_json<T: Body>(body: &serde_json::Value) -> Result<T, QueueError>
+{
+    let value: T = match serde_path_to_error::deserialize(body)
+    {
+        Ok(value) => value,
+        Err(err)  => { T::parts(body)?; return Err(_invalid(err)); },
+    };
+
+    let mut errors = Vec::new();
+
+    value.check(&mut errors);
+
+    if errors.is_empty() { Ok(value) } else { Err(QueueError::InvalidFields(errors)) }
+}
+
+fn _invalid(err: serde_path_to_error::Error<serde_json::Error>) -> QueueError
+{
+    let path = err.path().to_string();
+
+    QueueError::InvalidFields(vec![_field_error(path, err.into_inner().to_string())])
+}
+
+/// Parses `body` as the flattened part `T`, ignoring the fields of the rest of the body.
+fn _part<T: DeserializeOwned>(body: &serde_json::Value) -> Result<(), QueueError>
+{
+    serde_path_to_error::deserialize::<_, T>(body).map(|_| ()).map_err(_invalid)
+}
+
+/// Serde reports a missing field against the object holding it; name the field itself instead.
+fn _field_error(path: String, message: String) -> (String, String)
+{
+    let parent = if path == "." { String::new() } else { format!("{}.", path) };
+
+    match message.strip_prefix("missing field `").and_then(|rest| rest.strip_suffix('`'))
+    {
+        Some(field)                 => (format!("{}{}", parent, field), String::from("is required")),
+        None if parent.is_empty()   => (String::from("body"), message),
+        None                        => (path, message),
+    }
+}
+
+fn _not_empty(field: &str, value: &str, errors: &mut FieldErrors)
+{
+    if value.is_empty() { errors.push((String::from(field), String::from("must not be empty"))); }
+}
+
+/// Header names must be fields a selector can name, other than the built-in ones.
+fn _headers(headers: &std::collections::BTreeMap<String, String>, errors: &mut FieldErrors)
+{
+    for name in headers.keys()
+    {
+        let problem = match name.as_str()
+        {
+            reserved if selector::FIELDS.contains(&reserved) => "is reserved",
+            field if !selector::is_field(field)               => "must be a letter or \"_\" followed by letters, digits, \"_\", \".\" or \"-\"",
+            _                                                 => continue,
+        };
+
+        errors.push((format!("headers.{}", name), String::from(problem)));
+    }
+}
+
+/// Converts any serializable value to the `json` crate's value the responses are built from.
+pub fn to_json<T: Serialize>(value: &T) -> json::JsonValue
+{
+    json::parse(&serde_json::to_string(value).unwrap()).unwrap()
+}
+
+impl Body for QueueName
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("name", &self.name, errors);
+    }
+}
+
+impl Body for Subscribe
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("name", &self.name, errors);
+    }
+}
+
+/// The client a `/pub` or `/unpub` body names; `caller` when it names none.
+pub fn target(body: &Publisher, caller: &Client) -> Client
+{
+    body.client.as_ref().and_then(|address| Client::parse(address)).unwrap_or_else(|| caller.clone())
+}
+
+impl Body for Publisher
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("name", &self.name, errors);
+
+        if let Some(address) = &self.client
+        {
+            if Client::parse(address).is_none() { errors.push((String::from("client"), format!("\"{}\" is not host:port", address))); }
+        }
+    }
+}
+
+impl Body for Push
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("name", &self.name, errors);
+        self.message.check(errors);
+    }
+
+    fn parts(body: &serde_json::Value) -> Result<(), QueueError>
+    {
+        Message::parts(body).and_then(|_| _part::<Message>(body))
+    }
+}
+
+impl Body for Message
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        if let Some(key) = &self.properties.dedup_id { _not_empty("dedup_id", key, errors); }
+        if let Some(key) = &self.properties.group_id { _not_empty("group_id", key, errors); }
+
+        _headers(&self.properties.headers, errors);
+    }
+
+    fn parts(body: &serde_json::Value) -> Result<(), QueueError>
+    {
+        _part::<Properties>(body)
+    }
+}
+
+impl Body for ReplyQueue {}
+
+impl Body for Call
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("name", &self.name, errors);
+    }
+}
+
+impl Body for TransactionId
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("transaction", &self.transaction, errors);
+    }
+}
+
+impl Body for StagedPush
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("transaction", &self.transaction, errors);
+        self.push.check(errors);
+    }
+
+    fn parts(body: &serde_json::Value) -> Result<(), QueueError>
+    {
+        Push::parts(body).and_then(|_| _part::<Push>(body))
+    }
+}
+
+impl Body for Ack
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("name", &self.name, errors);
+    }
+}
+
+impl Body for StagedAck
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("transaction", &self.transaction, errors);
+        self.ack.check(errors);
+    }
+
+    fn parts(body: &serde_json::Value) -> Result<(), QueueError>
+    {
+        _part::<Ack>(body)
+    }
+}
+
+impl Body for Grant
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("name",      &self.name,      errors);
+        _not_empty("principal", &self.principal, errors);
+    }
+}
+
+impl Body for Role
+{
+    fn check(&self, errors: &mut FieldErrors)
+    {
+        _not_empty("principal", &self.principal, errors);
+        _not_empty("role",      &self.role,      errors);
+    }
+}
+
+/// Body of `POST /state`: the queues of a `GET /state` body, checked in depth by `persistence::restore`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct State
+{
+    pub queues: Vec<serde_json::Value>,
+}
+
+impl Body for State {}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// The `(field, problem)` pairs `T` rejects `body` with, fields only.
+    fn _fields<T: Body + fmt::Debug>(body: serde_json::Value) -> Vec<String>
+    {
+        match parse_json::<T>(&body)
+        {
+            Err(QueueError::InvalidFields(errors)) => errors.into_iter().map(|(field, _)| field).collect(),
+            other                                  => panic!("{} was not rejected by field: {:?}", body, other),
+        }
+    }
+
+    #[test]
+    fn valid_bodies_parse()
+    {
+        let push = parse_json::<Push>(&serde_json::json!({"name": "jobs", "data": {"n": 1}, "lifetime": 2.5, "priority": 3, "headers": {"region": "eu"}})).unwrap();
+
+        assert_eq!((push.name.as_str(), push.message.data.as_str()), ("jobs", r#"{"n":1}"#));
+        assert_eq!((push.message.lifetime, push.message.priority), (Some(2.5), Some(3)));
+        assert_eq!(push.message.properties.headers["region"], "eu");
+    }
+
+    #[test]
+    fn missing_fields_are_named()
+    {
+        assert_eq!(_fields::<Push>(serde_json::json!({"data": "x"})), vec!["name"]);
+        assert_eq!(_fields::<StagedAck>(serde_json::json!({"transaction": "t", "name": "jobs"})), vec!["id"]);
+        assert_eq!(_fields::<QueueName>(serde_json::json!([])), vec!["body"]);
+    }
+
+    #[test]
+    fn wrong_values_are_named_inside_flattened_parts()
+    {
+        assert_eq!(_fields::<Push>(serde_json::json!({"name": "jobs", "data": "x", "lifetime": -1})), vec!["lifetime"]);
+        assert_eq!(_fields::<Push>(serde_json::json!({"name": "jobs", "data": "x", "priority": "high"})), vec!["priority"]);
+        assert_eq!(_fields::<StagedPush>(serde_json::json!({"transaction": "t", "name": "jobs", "data": "x", "dedup_id": 5})), vec!["dedup_id"]);
+    }
+
+    #[test]
+    fn checks_report_every_field()
+    {
+        let fields = _fields::<Push>(serde_json::json!({"name": "", "data": "x", "group_id": "", "headers": {"priority": "1", "9lives": "y"}}));
+
+        assert_eq!(fields, vec!["name", "group_id", "headers.9lives", "headers.priority"]);
+        assert_eq!(_fields::<Publisher>(serde_json::json!({"name": "jobs", "client": "10.0.0.1:http"})), vec!["client"]);
+        assert_eq!(_fields::<Grant>(serde_json::json!({"name": "jobs", "principal": "", "permission": "read"})), vec!["permission"]);
+    }
+}
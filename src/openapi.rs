@@ -0,0 +1,184 @@
+//! The OpenAPI 3 document served at `/openapi.json`, built from what `config::routes` registers.
+//!
+//! Each route carries a `Doc`. Its body type is the one `Server::_handler` checks the request
+//! against, and its schemas come from the serde models, so the document and the validation
+//! cannot drift apart.
+
+use super::*;
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+
+type SchemaFn    = fn(&mut SchemaGenerator) -> Schema;
+pub type ParseFn = fn(&serde_json::Value) -> Result<Box<dyn std::any::Any>, QueueError>;
+
+/// What the route table states about a route besides its handler.
+#[derive(Clone)]
+pub struct Doc
+{
+    pub summary: &'static str,
+    /// Schema of the JSON body, and how it is parsed before the handler.
+    pub body:    Option<(SchemaFn, ParseFn)>,
+    /// Schema of a successful JSON answer.
+    pub answer:  Option<SchemaFn>,
+    /// Status of a successful answer; `Server::_handler` gives it to the route's `200`s.
+    pub status:  status::Status,
+    /// Content type of a successful answer.
+    pub content: &'static str,
+    /// Query string parameters, all optional strings.
+    pub query:   &'static [&'static str],
+}
+
+impl Doc
+{
+    pub fn new(summary: &'static str) -> Doc
+    {
+        Doc { summary, body: None, answer: None, status: status::Ok, content: "application/json", query: &[] }
+    }
+
+    pub fn body<T: model::Body + JsonSchema>(mut self) -> Doc
+    {
+        self.body = Some((_schema::<T>, _parse::<T>));
+        self
+    }
+
+    pub fn answer<T: JsonSchema>(mut self) -> Doc
+    {
+        self.answer = Some(_schema::<T>);
+        self
+    }
+
+    pub fn status(mut self, status: status::Status) -> Doc
+    {
+        self.status = status;
+        self
+    }
+
+    pub fn content(mut self, content: &'static str) -> Doc
+    {
+        self.content = content;
+        self
+    }
+
+    pub fn query(mut self, params: &'static [&'static str]) -> Doc
+    {
+        self.query = params;
+        self
+    }
+}
+
+fn _schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema
+{
+    gen.subschema_for::<T>()
+}
+
+fn _parse<T: model::Body>(body: &serde_json::Value) -> Result<Box<dyn std::any::Any>, QueueError>
+{
+    model::parse_json::<T>(body).map(|value| Box::new(value) as Box<dyn std::any::Any>)
+}
+
+struct Route
+{
+    path:   String,
+    method: String,
+    doc:    Doc,
+}
+
+lazy_static!
+{
+    static ref ROUTES: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+}
+
+/// Records a route for the document; `router_add_path` calls this for every route it adds.
+pub fn register(path: &str, method: &str, doc: &Doc)
+{
+    let mut routes = ROUTES.lock().unwrap();
+
+    routes.retain(|route| route.path != path || route.method != method);
+    routes.push(Route { path: String::from(path), method: String::from(method), doc: doc.clone() });
+}
+
+/// Router globs name parameters `:name`; OpenAPI wants `{name}`.
+fn _path(path: &str) -> (String, Vec<String>)
+{
+    let mut params = Vec::new();
+    let segments   = path.split('/').map(|segment| match segment.strip_prefix(':')
+    {
+        Some(name) => { params.push(String::from(name)); format!("{{{}}}", name) },
+        None       => String::from(segment),
+    }).collect::<Vec<String>>();
+
+    (segments.join("/"), params)
+}
+
+fn _content(content_type: &str, schema: Option<json::JsonValue>) -> json::JsonValue
+{
+    let mut media = json::object!{};
+
+    if let Some(schema) = schema { media["schema"] = schema; }
+
+    let mut content = json::object!{};
+
+    content[content_type] = media;
+
+    content
+}
+
+pub fn document() -> json::JsonValue
+{
+    let mut gen   = SchemaSettings::openapi3().into_generator();
+    let error     = model::to_json(&_schema::<model::ErrorBody>(&mut gen));
+    let mut paths = json::object!{};
+
+    for route in ROUTES.lock().unwrap().iter()
+    {
+        let (path, params) = _path(&route.path);
+        let doc            = &route.doc;
+        let mut parameters = json::JsonValue::new_array();
+
+        for name in params
+        {
+            parameters.push(json::object!{"name" => name, "in" => "path", "required" => true, "schema" => json::object!{"type" => "string"}}).unwrap();
+        }
+
+        for name in doc.query
+        {
+            parameters.push(json::object!{"name" => *name, "in" => "query", "required" => false, "schema" => json::object!{"type" => "string"}}).unwrap();
+        }
+
+        let answered      = doc.status.to_u16().to_string();
+        let mut operation = json::object!
+        {
+            "summary"    => doc.summary,
+            "parameters" => parameters,
+            "responses"  => json::object!
+            {
+                answered.as_str() => json::object!{"description" => doc.status.canonical_reason().unwrap_or("OK"), "content" => _content(doc.content, doc.answer.map(|schema| model::to_json(&schema(&mut gen))))},
+                "default" => json::object!{"description" => "Error", "content" => _content("application/json", Some(error.clone()))},
+            },
+        };
+
+        if let Some((schema, _)) = doc.body
+        {
+            operation["requestBody"] = json::object!{"required" => true, "content" => _content("application/json", Some(model::to_json(&schema(&mut gen))))};
+        }
+
+        if paths[&*path].is_null() { paths[&*path] = json::object!{}; }
+
+        paths[&*path][&*route.method] = operation;
+    }
+
+    json::object!
+    {
+        "openapi"    => "3.0.3",
+        "info"       => json::object!{"title" => env!("CARGO_PKG_NAME"), "version" => env!("CARGO_PKG_VERSION")},
+        "paths"      => paths,
+        "components" => json::object!{"schemas" => model::to_json(gen.definitions())},
+    }
+}
+
+/// `/openapi.json`.
+pub fn serve() -> IronResult<Response>
+{
+    Ok(Response::json(document(), status::Ok))
+}
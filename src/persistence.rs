@@ -0,0 +1,178 @@
+use super::*;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// File inside `data_dir` holding every queue.
+pub const SNAPSHOT: &str = "queues.json";
+
+fn _seconds(time: SystemTime) -> f64
+{
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+fn _time(value: &json::JsonValue) -> Option<SystemTime>
+{
+    value.as_f64().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()).and_then(|offset| UNIX_EPOCH.checked_add(offset))
+}
+
+fn _client(value: &json::JsonValue) -> Option<Client>
+{
+    Some(Client
+    {
+        host:     value["host"].as_str()?.to_string(),
+        port:     value["port"].as_u64()?,
+        identity: value["identity"].as_str().map(String::from),
+    })
+}
+
+fn _clients(value: &json::JsonValue) -> Option<Vec<Client>>
+{
+    value.members().map(_client).collect()
+}
+
+/// `None`, logged, when the payload cannot be read: the message cannot be saved.
+fn _message_to_json(queue: &str, message: &MSG) -> Option<json::JsonValue>
+{
+    let data = match message.data.text()
+    {
+        Ok(data) => data,
+        Err(err) =>
+        {
+            logging::event(logging::Level::Error, "message not saved", json::object!{"queue" => queue, "id" => message.id, "error" => err.to_string()});
+            return None;
+        },
+    };
+
+    Some(json::object!
+    {
+        "id"         => message.id,
+        "sender"     => message.sender.clone(),
+        "recipients" => message.recipients.clone(),
+        "created"    => _seconds(message.created),
+        "lifetime"   => _seconds(message.lifetime),
+        "data"       => data,
+        "active"     => message.active,
+        "priority"   => message.priority,
+        "properties" => model::to_json(&message.properties),
+    })
+}
+
+/// Snapshots written before messages had ids get their position instead.
+fn _message(index: usize, value: &json::JsonValue) -> Option<MSG>
+{
+    Some(MSG
+    {
+        id:         value["id"].as_u64().unwrap_or(index as u64 + 1),
+        sender:     _client(&value["sender"])?,
+        recipients: _clients(&value["recipients"])?,
+        created:    _time(&value["created"])?,
+        lifetime:   _time(&value["lifetime"])?,
+        data:       Payload::from(value["data"].as_str()?.to_string()),
+        active:     value["active"].as_bool()?,
+        priority:   value["priority"].as_usize()?,
+        properties: Properties
+        {
+            reply_to:       value["properties"]["reply_to"].as_str().map(String::from),
+            correlation_id: value["properties"]["correlation_id"].as_str().map(String::from),
+            dedup_id:       value["properties"]["dedup_id"].as_str().map(String::from),
+            group_id:       value["properties"]["group_id"].as_str().map(String::from),
+            headers:        value["properties"]["headers"].entries().filter_map(|(name, value)| value.as_str().map(|value| (String::from(name), String::from(value)))).collect(),
+        },
+    })
+}
+
+/// A message whose payload cannot be read is left out (see `_message_to_json`).
+pub fn queue_to_json(queue: &Queue) -> json::JsonValue
+{
+    json::object!
+    {
+        "name"        => queue.name.clone(),
+        "publishers"  => queue.publishers.clone(),
+        "subscribers" => queue.subscribers.clone(),
+        "selectors"   => model::to_json(&queue.selectors),
+        "data"        => queue.data.iter().filter_map(|message| _message_to_json(&queue.name, message)).collect::<Vec<json::JsonValue>>(),
+        "acl"         => queue.acl.clone(),
+    }
+}
+
+pub fn queue(value: &json::JsonValue) -> Option<Queue>
+{
+    let data   = value["data"].members().enumerate().map(|(index, message)| _message(index, message)).collect::<Option<Vec<MSG>>>()?;
+    let window = Duration::from_secs_f64(config::settings().queue.dedup_window);
+    let now    = SystemTime::now();
+    let dedup  = data.iter()
+        .filter_map(|message| Some((message.properties.dedup_id.clone()?, (message.without_data(), message.created + window))))
+        .filter(|(_, (_, until))| *until > now)
+        .collect();
+
+    Some(Queue
+    {
+        name:        value["name"].as_str()?.to_string(),
+        publishers:  _clients(&value["publishers"])?,
+        subscribers: _clients(&value["subscribers"])?,
+        selectors:   value["selectors"].entries().map(|(client, selector)| Some((String::from(client), selector.as_str()?.parse().ok()?))).collect::<Option<_>>()?,
+        next_id:     data.iter().map(|message| message.id).max().unwrap_or(0) + 1,
+        data,
+        acl:         acl::Acl::from_json(&value["acl"]),
+        temporary:   None,
+        dedup,
+        groups:      HashMap::new(),
+    })
+}
+
+/// Every queue in snapshot form, except reply queues: they die with their requester.
+pub fn dump(broker: &Broker) -> json::JsonValue
+{
+    let mut data = json::JsonValue::new_array();
+
+    for q in broker.queues().values()
+    {
+        let queue = q.lock().unwrap();
+
+        if queue.temporary.is_none() { data.push(queue_to_json(&queue)).unwrap(); }
+    }
+
+    data
+}
+
+/// Inserts every queue of a `dump`, replacing queues of the same name; nothing is inserted if one is malformed.
+pub fn restore(broker: &Broker, data: &json::JsonValue) -> Result<usize, String>
+{
+    let restored = data.members()
+        .map(|value| queue(value).ok_or_else(|| format!("malformed queue {}", value["name"])))
+        .collect::<Result<Vec<Queue>, String>>()?;
+    let count    = restored.len();
+
+    for queue in restored { broker.insert(queue); }
+
+    Ok(count)
+}
+
+/// Writes every queue to `dir`, replacing the previous snapshot atomically; returns the number of queues.
+pub fn flush(broker: &Broker, dir: &str) -> Result<usize, String>
+{
+    let data  = dump(broker);
+    let count = data.len();
+    let path  = Path::new(dir).join(SNAPSHOT);
+    let tmp   = Path::new(dir).join(format!("{}.tmp", SNAPSHOT));
+
+    fs::create_dir_all(dir).map_err(|e| format!("\"{}\": {}", dir, e))?;
+    fs::write(&tmp, data.dump()).map_err(|e| format!("\"{}\": {}", tmp.display(), e))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+
+    Ok(count)
+}
+
+/// Restores queues from the snapshot in `dir`, if there is one; returns the number of queues.
+pub fn load(broker: &Broker, dir: &str) -> Result<usize, String>
+{
+    let path = Path::new(dir).join(SNAPSHOT);
+
+    if !path.exists() { return Ok(0); }
+
+    let content = fs::read_to_string(&path).map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+    let data    = json::parse(&content).map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+
+    restore(broker, &data).map_err(|e| format!("\"{}\": {}", path.display(), e))
+}
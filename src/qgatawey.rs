@@ -0,0 +1,465 @@
+use super::*;
+
+lazy_static!
+{
+    static ref BROKER: Broker = Broker::new();
+}
+
+/// The broker served over HTTP.
+pub fn broker() -> &'static Broker
+{
+    &BROKER
+}
+
+pub fn queues() -> std::sync::MutexGuard<'static, broker::Queues>
+{
+    BROKER.queues()
+}
+
+pub fn _json_response_finalize(rx: std::sync::mpsc::Receiver<Result<json::JsonValue, QueueError>>) -> IronResult<Response> 
+{
+    match rx.recv().unwrap()
+    {
+        Ok(queues) => Ok(Response::json(queues, status::Ok)),
+        Err(err)   => Ok(err.response()),
+    }
+}
+
+/// Every queue `client` may read metadata of; message payloads only of queues it may subscribe to.
+pub fn queues_to_json(client: &Client) -> Result<json::JsonValue, QueueError>
+{
+    let mut data = json::object!{};
+
+    for (k, v) in queues().clone()
+    {
+        let mut queue = (*v.clone().lock().unwrap()).clone();
+
+        if queue.acl.allows(client, acl::Permission::ReadMetadata)
+        {
+            let payloads = queue.acl.allows(client, acl::Permission::Subscribe);
+
+            for message in queue.data.iter_mut()
+            {
+                message.data = if payloads { Payload::Plain(message.data.text()?) } else { Payload::Plain(String::new()) };
+            }
+
+            data[k.as_str()] = json::JsonValue::from(queue);
+
+            if !payloads
+            {
+                for message in data[k.as_str()]["data"].members_mut() { message.remove("data"); }
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+/// Audit trail of the caller, or of `principal` for operators, newest first.
+pub fn get_user_log(client: Client, query: urlencoded::QueryMap) -> IronResult<Response>
+{
+    let param     = |name: &str| query.get(name).and_then(|values| values.first()).cloned();
+    let principal = param("principal").unwrap_or_else(|| client.principal());
+
+    if principal != client.principal()
+    {
+        if let Err(err) = acl::GLOBAL.lock().unwrap().check(&client, acl::Permission::Manage, "broker")
+        {
+            return Ok(err.response());
+        }
+    }
+
+    match audit::Query::parse(&param)
+    {
+        Ok(filter) => Ok(Response::json(audit::query(&principal, &filter), status::Ok)),
+        Err(err)   => Ok(err.response()),
+    }
+}
+
+/// `/queues`: metadata of every readable queue, filtered by `prefix` and sorted by `sort`.
+pub fn list_queues(client: Client, query: urlencoded::QueryMap) -> IronResult<Response>
+{
+    let param = |name: &str| query.get(name).and_then(|values| values.first()).cloned();
+
+    match listing::QueueQuery::parse(&param)
+    {
+        Ok(filter) => Ok(Response::json(listing::queues(&client, &filter), status::Ok)),
+        Err(err)   => Ok(err.response()),
+    }
+}
+
+/// `/queue`: metadata of one queue, without its messages.
+pub fn queue_info(client: Client, query: urlencoded::QueryMap) -> IronResult<Response>
+{
+    let name = query.get("name").and_then(|values| values.first()).cloned().unwrap_or_default();
+
+    match broker().update(&name, |queue| listing::info(&client, queue))
+    {
+        Ok(info) => Ok(Response::json(info, status::Ok)),
+        Err(err) => Ok(err.response()),
+    }
+}
+
+/// `/messages`: one page of a queue's messages, oldest first; nothing is consumed.
+pub fn peek_messages(client: Client, query: urlencoded::QueryMap) -> IronResult<Response>
+{
+    let param = |name: &str| query.get(name).and_then(|values| values.first()).cloned();
+    let name  = param("name").unwrap_or_default();
+
+    match listing::MessageQuery::parse(&param).and_then(|filter| broker().update(&name, |queue| { queue.touch(&client); listing::messages(&client, queue, &filter) }))
+    {
+        Ok(page) => Ok(Response::json(page, status::Ok)),
+        Err(err) => Ok(err.response()),
+    }
+}
+
+pub fn new_queue(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::QueueName>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tr.send(broker().create(&client, &body.name).and_then(|_| queues_to_json(&client))).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+pub fn sub(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::Subscribe>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tr.send(broker().subscribe_with(&client, &body.name, body.selector).map(json::JsonValue::from)).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+pub fn unsub(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::QueueName>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tr.send(broker().unsubscribe(&client, &body.name).map(json::JsonValue::from)).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+fn _pub_or_unpub(client: Client, formdata: model::Formdata, publ: bool) -> IronResult<Response>
+{
+    let body = match model::parse::<model::Publisher>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+    let target   = model::target(&body, &client);
+
+    thread::spawn(move || {
+        let result = broker().update(&body.name, |queue| if publ { queue.add_publisher(&client, target) } else { queue.remove_publisher(&client, target) });
+
+        tr.send(result.map(json::JsonValue::from)).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+pub fn _pub(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    _pub_or_unpub(client, formdata, true)
+}
+
+pub fn unpub(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    _pub_or_unpub(client, formdata, false)
+}
+
+pub fn push_in_queue(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::Push>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let message = body.message;
+        let result  = broker().publish_with(&client, &body.name, message.data, message.lifetime, message.priority, message.properties);
+
+        tr.send(result.map(json::JsonValue::from)).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+/// `/consume`: takes the next message waiting for the caller, `null` when there is none yet.
+pub fn consume(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::QueueName>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tr.send(broker().consume(&client, &body.name).map(|message| message.map_or(json::JsonValue::Null, json::JsonValue::from))).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+/// `/ack`: takes message `id` off the queue for the caller, whatever its priority.
+pub fn ack(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::Ack>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tr.send(broker().ack(&client, &body.name, body.id).map(json::JsonValue::from)).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+fn _grant_or_revoke(client: Client, formdata: model::Formdata, grant: bool) -> IronResult<Response>
+{
+    let body = match model::parse::<model::Grant>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (principal, permission) = (body.principal, body.permission);
+
+        tr.send(broker().update(&body.name, |queue| if grant { queue.grant(&client, principal, permission) } else { queue.revoke(&client, principal, permission) })).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+pub fn grant(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    _grant_or_revoke(client, formdata, true)
+}
+
+pub fn revoke(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    _grant_or_revoke(client, formdata, false)
+}
+
+pub fn assign_role(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::Role>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+
+    if let Err(err) = acl::GLOBAL.lock().unwrap().check(&client, acl::Permission::Manage, "broker")
+    {
+        return Ok(err.response());
+    }
+
+    Ok(Response::json(json::JsonValue::from(acl::assign_role(body.principal, body.role)), status::Ok))
+}
+
+/// Creates an exclusive reply queue for the caller; answers its generated name.
+pub fn reply_queue(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::ReplyQueue>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+
+    match broker().create_reply_queue(&client, body.idle_timeout)
+    {
+        Ok(name) => Ok(Response::json(model::to_json(&model::QueueName { name }), status::Ok)),
+        Err(err) => Ok(err.response()),
+    }
+}
+
+/// Publishes a request and blocks until its reply arrives or `timeout` passes.
+pub fn request(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::Call>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let result = broker().request(&client, &body.name, body.data, body.lifetime, body.priority, body.timeout);
+
+        tr.send(result.map(json::JsonValue::from)).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+/// Opens a transaction for the caller; stage into it with `/transaction/push` and `/transaction/ack`.
+pub fn begin_transaction(client: Client) -> IronResult<Response>
+{
+    Ok(Response::json(model::to_json(&model::TransactionId { transaction: broker().begin(&client) }), status::Ok))
+}
+
+fn _staged(transaction: String, result: Result<usize, QueueError>) -> IronResult<Response>
+{
+    match result
+    {
+        Ok(operations) => Ok(Response::json(model::to_json(&model::Staged { transaction, operations }), status::Ok)),
+        Err(err)       => Ok(err.response()),
+    }
+}
+
+/// Stages a push; nothing is published before `/transaction/commit`.
+pub fn stage_push(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::StagedPush>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let operation = transaction::Operation::Push { client, queue: body.push.name, message: body.push.message };
+
+    _staged(body.transaction.clone(), broker().stage(&body.transaction, operation))
+}
+
+/// Stages an ack; the message stays waiting before `/transaction/commit`.
+pub fn stage_ack(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::StagedAck>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+
+    let operation = transaction::Operation::Ack { client, queue: body.ack.name, id: body.ack.id };
+
+    _staged(body.transaction.clone(), broker().stage(&body.transaction, operation))
+}
+
+/// Applies a transaction: every staged operation or, if one fails, none of them.
+pub fn commit(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::TransactionId>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tr.send(broker().commit(&client, &body.transaction).map(json::JsonValue::from)).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+pub fn rollback(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::TransactionId>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+
+    _staged(body.transaction.clone(), broker().rollback(&client, &body.transaction))
+}
+
+/// Removes a queue and everything in it; requires `manage` on the queue.
+pub fn delete_queue(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::QueueName>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+    let (tr, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tr.send(broker().delete(&client, &body.name).map(|messages| model::to_json(&model::Deleted { name: body.name.clone(), messages }))).unwrap();
+    });
+
+    _json_response_finalize(rx)
+}
+
+/// `GET /state`: every queue in snapshot form, for operators.
+pub fn dump_state(client: Client) -> IronResult<Response>
+{
+    if let Err(err) = acl::GLOBAL.lock().unwrap().check(&client, acl::Permission::Manage, "broker")
+    {
+        return Ok(err.response());
+    }
+
+    Ok(Response::json(json::object!{"queues" => persistence::dump(broker())}, status::Ok))
+}
+
+/// `POST /state`: loads a `dump_state` body, replacing queues of the same name.
+pub fn restore_state(client: Client, formdata: model::Formdata) -> IronResult<Response>
+{
+    let body = match model::parse::<model::State>(formdata)
+    {
+        Ok(body) => body,
+        Err(err) => return Ok(err.response()),
+    };
+
+    if let Err(err) = acl::GLOBAL.lock().unwrap().check(&client, acl::Permission::Manage, "broker")
+    {
+        return Ok(err.response());
+    }
+
+    match persistence::restore(broker(), &model::to_json(&body.queues))
+    {
+        Ok(count) =>
+        {
+            logging::event(logging::Level::Info, "state restored", json::object!{"client" => client.to_string(), "queues" => count});
+            Ok(Response::json(model::to_json(&model::Restored { restored: count }), status::Ok))
+        },
+        Err(err)  => Ok(QueueError::Invalid(err).response()),
+    }
+}
+
+/// Runs `Broker::expire` on the served broker once a second, forever.
+pub fn expire_all()
+{
+    loop
+    {
+        thread::sleep(Duration::from_secs(1));
+        broker().expire();
+    }
+}
+
+pub fn full_map(client: Client) -> IronResult<Response>
+{
+    match queues_to_json(&client)
+    {
+        Ok(data) => IronResult::Ok(Response::json(data, status::Ok)),
+        Err(err) => IronResult::Ok(err.response()),
+    }
+}
@@ -0,0 +1,696 @@
+use super::*;
+
+use std::collections::BTreeMap;
+
+
+/// What makes a reply queue temporary: only `owner` reads it, and it goes away once the owner
+/// has not read it for `idle`.
+#[derive(Debug, Clone)]
+pub struct Temporary
+{
+    /// Principal of the requester that created it.
+    pub owner:     String,
+    pub idle:      Duration,
+    pub last_seen: Instant,
+}
+
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Queue 
+{
+    pub name:        String,
+    #[serde(rename = "publisher")]
+    pub publishers:  Vec<Client>,
+    pub subscribers: Vec<Client>,
+    /// Selector of each subscriber that has one, by `Client` as written; others get every message.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub selectors:   BTreeMap<String, selector::Selector>,
+    pub data:        Vec<MSG>,
+    pub acl:         acl::Acl,
+    #[serde(skip)]
+    pub temporary:   Option<Temporary>,
+    #[serde(skip)]
+    pub(crate) next_id: u64,
+    /// The message published with each `dedup_id`, without its payload, and when the key is forgotten.
+    #[serde(skip)]
+    pub(crate) dedup:   HashMap<String, (MSG, SystemTime)>,
+    /// Subscriber each message group is handed to while the group has messages left.
+    #[serde(skip)]
+    pub(crate) groups:  HashMap<String, Client>,
+}
+
+
+impl Queue 
+{
+    pub fn new(name: String, creator: Client) -> Queue 
+    {
+        let mut this = Queue 
+        {
+            name,
+            publishers:  Vec::new(),
+            subscribers: Vec::new(),
+            selectors:   BTreeMap::new(),
+            data:        Vec::new(),
+            acl:         acl::Acl::for_queue(&creator),
+            temporary:   None,
+            next_id:     1,
+            dedup:       HashMap::new(),
+            groups:      HashMap::new(),
+        };
+
+        this.publishers.push(creator);
+
+        this
+    }
+
+    /// Exclusive reply queue of `creator`, who is its only subscriber; anyone may publish to it.
+    pub fn reply(name: String, creator: Client, idle: Duration) -> Queue
+    {
+        let mut this = Queue::new(name, creator.clone());
+
+        this.acl       = acl::Acl::for_reply_queue(&creator);
+        this.temporary = Some(Temporary { owner: creator.principal(), idle, last_seen: Instant::now() });
+        this.publishers.clear();
+        this.subscribers.push(creator);
+
+        this
+    }
+
+    /// Keeps a reply queue alive while its owner is reading it.
+    pub fn touch(&mut self, reader: &Client)
+    {
+        if let Some(temporary) = self.temporary.as_mut()
+        {
+            if temporary.owner == reader.principal() { temporary.last_seen = Instant::now(); }
+        }
+    }
+
+    /// Whether this is a reply queue its owner has abandoned.
+    pub fn is_idle(&self, now: Instant) -> bool
+    {
+        self.temporary.as_ref().is_some_and(|temporary| now.duration_since(temporary.last_seen) >= temporary.idle)
+    }
+
+    /// `data` comes packed, since packing may compress or spill it: see `Broker::publish_with`.
+    pub fn push(&mut self, data: Payload, publisher: Client, lifetime: Option<f64>, priority: Option<usize>, properties: message::Properties) -> Result<MSG, QueueError>
+    {
+        let (msg, fresh) = self.append(data, publisher, lifetime, priority, properties)?;
+
+        if fresh { self.published(&msg); }
+
+        Ok(msg)
+    }
+
+    /// `push` without the metrics and audit of a publish, which `published` records once the
+    /// message is visible; also tells whether the message is new rather than a deduplicated one.
+    pub(crate) fn append(&mut self, data: Payload, publisher: Client, lifetime: Option<f64>, priority: Option<usize>, properties: message::Properties) -> Result<(MSG, bool), QueueError>
+    {
+        self._append(data, publisher, lifetime, priority, properties, &config::settings().queue)
+    }
+
+    /// `append` under `limits` rather than the installed settings.
+    fn _append(&mut self, data: Payload, publisher: Client, lifetime: Option<f64>, priority: Option<usize>, properties: message::Properties, limits: &config::QueueLimits) -> Result<(MSG, bool), QueueError>
+    {
+        let lt = SystemTime::now() + Duration::from_secs_f64(lifetime.unwrap_or(limits.default_lifetime));
+
+        if !self.publishers.contains(&publisher) && !self.acl.allows(&publisher, acl::Permission::Publish)
+        {
+            return Err(QueueError::Forbidden(format!("\"{}\" is not publisher", publisher)));
+        }
+
+        if let Some(key) = &properties.dedup_id
+        {
+            self._forget(SystemTime::now());
+
+            if let Some((original, _)) = self.dedup.get(key)
+            {
+                let held = self.data.iter().find(|message| message.id == original.id);
+
+                metrics::record(&self.name, |counters| counters.deduplicated += 1);
+                logging::event(logging::Level::Debug, "duplicate dropped", json::object!{"queue" => self.name.clone(), "dedup_id" => key.clone(), "original" => original.id});
+
+                return match limits.dedup
+                {
+                    config::DedupMode::Ignore => Ok((held.unwrap_or(original).clone(), false)),
+                    config::DedupMode::Reject => Err(QueueError::Duplicate(original.id)),
+                };
+            }
+        }
+
+        if let Some(max) = limits.max_messages
+        {
+            if self.data.len() >= max { return Err(QueueError::QueueFull(format!("\"{}\" is full ({} messages)", self.name, max))); }
+        }
+
+        let mut msg = MSG::new(self.next_id, data, publisher, Vec::new(), lt, priority);
+
+        msg.properties = properties;
+        msg.recipients = self.subscribers.iter().filter(|subscriber| self.selects(subscriber, &msg)).cloned().collect();
+
+        self.next_id += 1;
+        self.data.push(msg.clone());
+
+        if let Some(key) = &msg.properties.dedup_id { self.dedup.insert(key.clone(), (msg.without_data(), msg.created + Duration::from_secs_f64(limits.dedup_window))); }
+
+        Ok((msg, true))
+    }
+
+    pub(crate) fn published(&self, msg: &MSG)
+    {
+        metrics::record(&self.name, |counters| counters.publish(SystemTime::now()));
+        audit::record(&msg.sender, audit::Action::Published, &self.name);
+    }
+
+    /// Whether `subscriber`'s selector, if any, matches `message`.
+    pub fn selects(&self, subscriber: &Client, message: &MSG) -> bool
+    {
+        self.selectors.get(&subscriber.to_string()).is_none_or(|selector| selector.matches(message))
+    }
+
+    pub fn sub(&mut self, subscriber: Client) -> Result<Vec<Client>, QueueError>
+    {
+        self.sub_with(subscriber, None)
+    }
+
+    /// `sub` that only addresses `subscriber` the messages `selector` matches.
+    pub fn sub_with(&mut self, subscriber: Client, selector: Option<selector::Selector>) -> Result<Vec<Client>, QueueError>
+    {
+        self.acl.check(&subscriber, acl::Permission::Subscribe, &self.name)?;
+
+        if let Some(temporary) = &self.temporary
+        {
+            if temporary.owner != subscriber.principal() { return Err(QueueError::Forbidden(format!("\"{}\" is an exclusive reply queue", self.name))); }
+        }
+
+        match self.subscribers.iter().position(|user| *user == subscriber)
+        {
+            Some(_) => Err(QueueError::AlreadyExists(format!("User \"{}\" allready subscriber of queue.", subscriber))),
+            None    => 
+            {
+                logging::event(logging::Level::Info, "subscriber joined", json::object!{"queue" => self.name.clone(), "client" => subscriber.to_string(), "selector" => selector.as_ref().map(|s| s.to_string())});
+                audit::record(&subscriber, audit::Action::Subscribed, &self.name);
+                self._select(&subscriber, selector);
+                self.subscribers.push(subscriber);
+                Ok(self.subscribers.clone())
+            }
+        }
+    }
+
+    fn _select(&mut self, subscriber: &Client, selector: Option<selector::Selector>)
+    {
+        match selector
+        {
+            Some(selector) => self.selectors.insert(subscriber.to_string(), selector),
+            None           => self.selectors.remove(&subscriber.to_string()),
+        };
+    }
+
+    /// Replaces the selector of subscriber `subscriber`, `None` meaning every message; applies to
+    /// messages pushed from now on.
+    pub fn select(&mut self, subscriber: &Client, selector: Option<selector::Selector>) -> Result<Vec<Client>, QueueError>
+    {
+        if !self.subscribers.contains(subscriber)
+        {
+            return Err(QueueError::NotFound(format!("User \"{}\" is not subscriber of queue.", subscriber)));
+        }
+
+        self._select(subscriber, selector);
+
+        Ok(self.subscribers.clone())
+    }
+
+    pub fn unsub(&mut self, subscriber: Client) -> Result<Vec<Client>, QueueError>
+    {
+        match self.subscribers.iter().position(|user| *user == subscriber)
+        {
+            Some(index) => 
+            {
+                logging::event(logging::Level::Info, "subscriber left", json::object!{"queue" => self.name.clone(), "client" => subscriber.to_string()});
+                audit::record(&subscriber, audit::Action::Unsubscribed, &self.name);
+                self.groups.retain(|_, holder| *holder != subscriber);
+                self.selectors.remove(&subscriber.to_string());
+                self.subscribers.remove(index);
+                Ok(self.subscribers.clone())
+            },
+            None => Err(QueueError::NotFound(format!("User \"{}\" is not subscriber of queue.", subscriber))),
+        }
+    }
+
+    pub fn add_publisher(&mut self, actor: &Client, publisher: Client) -> Result<Vec<Client>, QueueError>
+    {
+        self.acl.check(actor, acl::Permission::Manage, &self.name)?;
+
+        match self.publishers.iter().position(|user| *user == publisher)
+        {
+            Some(_) => Err(QueueError::AlreadyExists(format!("User \"{}\" allready publisher of queue.", publisher))),
+            None    => 
+            {
+                logging::event(logging::Level::Info, "publisher added", json::object!{"queue" => self.name.clone(), "client" => publisher.to_string(), "by" => actor.to_string()});
+                self.publishers.push(publisher);
+                Ok(self.publishers.clone())
+            }
+        }
+    }
+
+    /// Anyone may step down as publisher; removing somebody else requires `manage`.
+    pub fn remove_publisher(&mut self, actor: &Client, publisher: Client) -> Result<Vec<Client>, QueueError>
+    {
+        if *actor != publisher { self.acl.check(actor, acl::Permission::Manage, &self.name)?; }
+
+        match self.publishers.iter().position(|user| *user == publisher)
+        {
+            Some(index) => 
+            {
+                logging::event(logging::Level::Info, "publisher removed", json::object!{"queue" => self.name.clone(), "client" => publisher.to_string(), "by" => actor.to_string()});
+                self.publishers.remove(index);
+                Ok(self.publishers.clone())
+            },
+            None       => Err(QueueError::NotFound(format!("User \"{}\" is not publisher of queue.", publisher))),
+        }
+    }
+
+    /// Index of the oldest live message of every group among those addressed to `subscriber`: the
+    /// only one of its group `subscriber` may take. A message of the group it will never get, e.g.
+    /// one its selector refused, does not hold it up.
+    fn _group_heads(&self, now: SystemTime, subscriber: &Client) -> HashMap<String, usize>
+    {
+        let mut heads = HashMap::new();
+
+        for (index, message) in self.data.iter().enumerate().filter(|(_, message)| message.active && message.lifetime > now && message.recipients.contains(subscriber))
+        {
+            if let Some(group) = &message.properties.group_id { heads.entry(group.clone()).or_insert(index); }
+        }
+
+        heads
+    }
+
+    /// Takes the highest-priority, then oldest, message addressed to `subscriber`; it stays
+    /// active until every subscriber it was addressed to has taken it.
+    ///
+    /// A message with a `group_id` is taken by one subscriber only, as in a work queue, and only
+    /// once every older message of its group addressed to that subscriber is gone. The first
+    /// subscriber to take from a group holds it until the group runs out of live messages for it,
+    /// so a group is consumed in publish order by one subscriber at a time while other groups go
+    /// to whoever asks.
+    pub fn consume(&mut self, subscriber: &Client, now: SystemTime) -> Result<Option<MSG>, QueueError>
+    {
+        if !self.subscribers.contains(subscriber)
+        {
+            return Err(QueueError::Forbidden(format!("User \"{}\" is not subscriber of queue.", subscriber)));
+        }
+
+        let heads = self._group_heads(now, subscriber);
+        let next  = self.data.iter().enumerate()
+            .filter(|(index, message)| message.active && message.lifetime > now && message.recipients.contains(subscriber) && self._in_turn(&heads, *index, subscriber))
+            .max_by(|(i, a), (j, b)| a.priority.cmp(&b.priority).then(j.cmp(i)))
+            .map(|(index, _)| index);
+
+        let message = match next
+        {
+            Some(index) => self._deliver(index, subscriber, now)?,
+            None        => return Ok(None),
+        };
+
+        metrics::record(&self.name, |counters| counters.consumed += 1);
+        audit::record(subscriber, audit::Action::Consumed, &self.name);
+
+        Ok(Some(message))
+    }
+
+    /// Takes message `id` for `subscriber` the way `consume` would, whatever its priority; it must
+    /// still be waiting for `subscriber`. Metrics and audit are left to `acked`.
+    pub(crate) fn take(&mut self, subscriber: &Client, id: u64, now: SystemTime) -> Result<MSG, QueueError>
+    {
+        if !self.subscribers.contains(subscriber)
+        {
+            return Err(QueueError::Forbidden(format!("User \"{}\" is not subscriber of queue.", subscriber)));
+        }
+
+        let index   = self.data.iter().position(|message| message.id == id)
+            .ok_or_else(|| QueueError::NotFound(format!("\"{}\" has no message {}", self.name, id)))?;
+        let message = &self.data[index];
+
+        if !message.active || message.lifetime <= now || !message.recipients.contains(subscriber)
+        {
+            return Err(QueueError::Expired(format!("message {} of \"{}\" is no longer waiting for \"{}\"", id, self.name, subscriber)));
+        }
+
+        if !self._in_turn(&self._group_heads(now, subscriber), index, subscriber)
+        {
+            return Err(QueueError::Forbidden(format!("message {} of \"{}\" waits for older messages of its group", id, self.name)));
+        }
+
+        self._deliver(index, subscriber, now)
+    }
+
+    /// Whether message `index` may go to `subscriber` as far as its group is concerned: it must be
+    /// the oldest live message of the group addressed to `subscriber` (see `_group_heads`), and the
+    /// group free or held by `subscriber`.
+    fn _in_turn(&self, heads: &HashMap<String, usize>, index: usize, subscriber: &Client) -> bool
+    {
+        match &self.data[index].properties.group_id
+        {
+            Some(group) => heads.get(group) == Some(&index) && self.groups.get(group).is_none_or(|holder| holder == subscriber),
+            None        => true,
+        }
+    }
+
+    /// Hands message `index` to `subscriber`; a grouped message goes to nobody else, and its group
+    /// stays with `subscriber` while it has live messages. A payload that cannot be read fails with
+    /// `Internal` and leaves the message as it was.
+    fn _deliver(&mut self, index: usize, subscriber: &Client, now: SystemTime) -> Result<MSG, QueueError>
+    {
+        let text    = self.data[index].data.text()?;
+        let message = &mut self.data[index];
+
+        message.recipients.retain(|recipient| recipient != subscriber);
+
+        if message.recipients.is_empty() || message.properties.group_id.is_some()
+        {
+            message.recipients.clear();
+            message.active = false;
+        }
+
+        let message = MSG { data: Payload::Plain(text), ..message.clone() };
+
+        if let Some(group) = &message.properties.group_id
+        {
+            self.groups.insert(group.clone(), subscriber.clone());
+            self._release_groups(now);
+        }
+
+        Ok(message)
+    }
+
+    /// Acknowledges message `id` for `subscriber`: it is taken off the queue for them.
+    pub fn ack(&mut self, subscriber: &Client, id: u64) -> Result<MSG, QueueError>
+    {
+        let message = self.take(subscriber, id, SystemTime::now())?;
+
+        self.acked(subscriber);
+
+        Ok(message)
+    }
+
+    pub(crate) fn acked(&self, subscriber: &Client)
+    {
+        metrics::record(&self.name, |counters| counters.acked += 1);
+        audit::record(subscriber, audit::Action::Acked, &self.name);
+    }
+
+    /// Lets go of groups that have no live message left for the subscriber holding them.
+    fn _release_groups(&mut self, now: SystemTime)
+    {
+        let data = &self.data;
+
+        self.groups.retain(|group, holder| data.iter().any(|message|
+        {
+            message.active && message.lifetime > now && message.properties.group_id.as_ref() == Some(group) && message.recipients.contains(holder)
+        }));
+    }
+
+    /// Active messages that have not outlived their lifetime.
+    pub fn depth(&self, now: SystemTime) -> usize
+    {
+        self.data.iter().filter(|message| message.active && message.lifetime > now).count()
+    }
+
+    /// Approximate bytes its messages take in memory.
+    pub fn bytes(&self) -> usize
+    {
+        self.data.iter().map(MSG::footprint).sum()
+    }
+
+    /// Moves the payloads of messages `ids` to files in `dir`, stopping at the first that cannot
+    /// be written; returns how many bytes of memory that freed.
+    pub(crate) fn page_out(&mut self, ids: &[u64], dir: &std::path::Path) -> usize
+    {
+        let mut freed = 0;
+
+        for message in self.data.iter_mut().filter(|message| ids.contains(&message.id) && message.data.stored_len() > 0)
+        {
+            let blob = match message.data.text().and_then(|text| blob::Blob::write(dir, &text).map_err(|err| QueueError::Internal(err.to_string())))
+            {
+                Ok(blob) => blob,
+                Err(err) =>
+                {
+                    logging::event(logging::Level::Warn, "payload not paged out", json::object!{"queue" => self.name.clone(), "id" => message.id, "error" => err.to_string()});
+                    break;
+                },
+            };
+
+            freed       += message.data.stored_len();
+            message.data = Payload::Spilled(Arc::new(blob));
+        }
+
+        freed
+    }
+
+    /// Drops every message; requires `manage`. Returns how many were dropped.
+    pub fn purge(&mut self, actor: &Client) -> Result<usize, QueueError>
+    {
+        self.acl.check(actor, acl::Permission::Manage, &self.name)?;
+
+        let purged = self.data.len();
+
+        self.data.clear();
+        self.dedup.clear();
+        self.groups.clear();
+        logging::event(logging::Level::Info, "queue purged", json::object!{"queue" => self.name.clone(), "by" => actor.to_string(), "messages" => purged});
+
+        Ok(purged)
+    }
+
+    /// Drops the `dedup_id`s of messages published more than `queue.dedup_window` before `now`.
+    fn _forget(&mut self, now: SystemTime)
+    {
+        self.dedup.retain(|_, (_, until)| *until > now);
+    }
+
+    /// Drops messages past their lifetime, and those every recipient has taken, so that they no
+    /// longer take memory; returns how many expired.
+    pub fn expire(&mut self, now: SystemTime) -> usize
+    {
+        let mut expired = 0;
+
+        self._forget(now);
+
+        for message in self.data.iter_mut().filter(|message| message.active && message.lifetime <= now)
+        {
+            message.active = false;
+            expired       += 1;
+
+            logging::event(logging::Level::Info, "message expired", json::object!{"queue" => self.name.clone(), "sender" => message.sender.to_string(), "priority" => message.priority});
+        }
+
+        if expired > 0 { metrics::record(&self.name, |counters| counters.expired += expired as u64); }
+
+        self.data.retain(|message| message.active);
+        self._release_groups(now);
+
+        expired
+    }
+
+    pub fn grant(&mut self, actor: &Client, principal: String, permission: acl::Permission) -> Result<json::JsonValue, QueueError>
+    {
+        self.acl.check(actor, acl::Permission::Manage, &self.name)?;
+        self.acl.grant(principal, permission);
+
+        Ok(json::JsonValue::from(self.acl.clone()))
+    }
+
+    pub fn revoke(&mut self, actor: &Client, principal: String, permission: acl::Permission) -> Result<json::JsonValue, QueueError>
+    {
+        self.acl.check(actor, acl::Permission::Manage, &self.name)?;
+
+        if self.acl.revoke(&principal, permission)
+        {
+            Ok(json::JsonValue::from(self.acl.clone()))
+        } else {
+            Err(QueueError::NotFound(format!("\"{}\" has no \"{}\" grant", principal, permission)))
+        }
+    }
+}
+
+
+impl std::convert::From<Queue> for json::JsonValue
+{
+    fn from(queue: Queue) -> Self
+    {
+        model::to_json(&queue)
+    }
+}
+
+
+impl fmt::Display for Queue 
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        write!(f, "{}", json::JsonValue::from(self.clone()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::testing;
+
+    fn _grouped(group: &str) -> Properties
+    {
+        Properties { group_id: Some(String::from(group)), ..Properties::default() }
+    }
+
+    fn _taken(queue: &mut Queue, subscriber: &Client) -> Option<String>
+    {
+        queue.consume(subscriber, SystemTime::now()).unwrap().map(|message| message.data.to_string())
+    }
+
+    /// A queue of `publisher` with `subscribers`, holding `messages` as (data, group, priority).
+    fn _queue(subscribers: &[&Client], messages: &[(&str, &str, usize)]) -> Queue
+    {
+        let publisher = testing::client(1);
+        let mut queue = Queue::new(String::from("jobs"), publisher.clone());
+
+        for subscriber in subscribers { queue.sub((*subscriber).clone()).unwrap(); }
+
+        for (data, group, priority) in messages
+        {
+            queue.push(String::from(*data).into(), publisher.clone(), None, Some(*priority), _grouped(group)).unwrap();
+        }
+
+        queue
+    }
+
+    #[test]
+    fn group_is_consumed_in_publish_order_whatever_the_priority()
+    {
+        let consumer  = testing::client(2);
+        let mut queue = _queue(&[&consumer], &[("a1", "a", 0), ("a2", "a", 9), ("a3", "a", 5)]);
+
+        assert_eq!(_taken(&mut queue, &consumer).as_deref(), Some("a1"));
+        assert_eq!(_taken(&mut queue, &consumer).as_deref(), Some("a2"));
+        assert_eq!(_taken(&mut queue, &consumer).as_deref(), Some("a3"));
+        assert_eq!(_taken(&mut queue, &consumer), None);
+    }
+
+    #[test]
+    fn group_goes_to_one_subscriber_while_other_groups_go_to_others()
+    {
+        let (first, second) = (testing::client(2), testing::client(3));
+        let mut queue       = _queue(&[&first, &second], &[("a1", "a", 0), ("a2", "a", 0), ("b1", "b", 0), ("b2", "b", 0)]);
+
+        assert_eq!(_taken(&mut queue, &first).as_deref(),  Some("a1"));
+        assert_eq!(_taken(&mut queue, &second).as_deref(), Some("b1"));
+        assert_eq!(_taken(&mut queue, &second).as_deref(), Some("b2"));
+        assert_eq!(_taken(&mut queue, &second), None);
+        assert_eq!(_taken(&mut queue, &first).as_deref(),  Some("a2"));
+    }
+
+    #[test]
+    fn group_is_released_once_it_runs_out()
+    {
+        let (first, second) = (testing::client(2), testing::client(3));
+        let publisher       = testing::client(1);
+        let mut queue       = _queue(&[&first, &second], &[("a1", "a", 0)]);
+
+        assert_eq!(_taken(&mut queue, &first).as_deref(), Some("a1"));
+        assert!(queue.groups.is_empty());
+
+        queue.push(String::from("a2").into(), publisher, None, None, _grouped("a")).unwrap();
+
+        assert_eq!(_taken(&mut queue, &second).as_deref(), Some("a2"));
+        assert_eq!(_taken(&mut queue, &first), None);
+    }
+
+    #[test]
+    fn ack_refuses_a_message_behind_older_ones_of_its_group()
+    {
+        let consumer  = testing::client(2);
+        let mut queue = _queue(&[&consumer], &[("a1", "a", 0), ("a2", "a", 0)]);
+
+        assert!(matches!(queue.ack(&consumer, 2), Err(QueueError::Forbidden(_))));
+        assert_eq!(queue.ack(&consumer, 1).unwrap().data.to_string(), "a1");
+        assert_eq!(queue.ack(&consumer, 2).unwrap().data.to_string(), "a2");
+    }
+
+    #[test]
+    fn expired_head_lets_the_rest_of_its_group_through()
+    {
+        let consumer  = testing::client(2);
+        let publisher = testing::client(1);
+        let mut queue = _queue(&[&consumer], &[]);
+
+        queue.push(String::from("a1").into(), publisher.clone(), Some(0.001), None, _grouped("a")).unwrap();
+        queue.push(String::from("a2").into(), publisher,         None,        None, _grouped("a")).unwrap();
+        thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(_taken(&mut queue, &consumer).as_deref(), Some("a2"));
+    }
+
+    #[test]
+    fn group_head_is_the_oldest_message_for_the_subscriber()
+    {
+        let (picky, anyone) = (testing::client(2), testing::client(3));
+        let publisher       = testing::client(1);
+        let mut queue       = _queue(&[&anyone], &[]);
+        let region          = |name: &str| Properties { headers: vec![(String::from("region"), String::from(name))].into_iter().collect(), .._grouped("a") };
+
+        queue.sub_with(picky.clone(), Some("region = 'eu'".parse().unwrap())).unwrap();
+        queue.push(String::from("us1").into(), publisher.clone(), None, None, region("us")).unwrap();
+        queue.push(String::from("eu1").into(), publisher.clone(), None, None, region("eu")).unwrap();
+        queue.push(String::from("eu2").into(), publisher,         None, None, region("eu")).unwrap();
+
+        assert_eq!(_taken(&mut queue, &picky).as_deref(), Some("eu1"));
+        assert_eq!(_taken(&mut queue, &anyone), None);
+        assert_eq!(_taken(&mut queue, &picky).as_deref(), Some("eu2"));
+        assert_eq!(_taken(&mut queue, &anyone).as_deref(), Some("us1"));
+    }
+
+    /// Publishes `data` with `dedup_id` "once" to `queue` under `mode`.
+    fn _once(queue: &mut Queue, data: &str, mode: config::DedupMode) -> Result<(MSG, bool), QueueError>
+    {
+        let limits = config::QueueLimits { dedup: mode, ..config::QueueLimits::default() };
+        let once   = Properties { dedup_id: Some(String::from("once")), ..Properties::default() };
+
+        queue._append(String::from(data).into(), testing::client(1), None, None, once, &limits)
+    }
+
+    #[test]
+    fn ignored_duplicates_answer_the_original_even_once_it_is_gone()
+    {
+        let consumer      = testing::client(2);
+        let mut queue     = _queue(&[&consumer], &[]);
+        let (original, _) = _once(&mut queue, "first", config::DedupMode::Ignore).unwrap();
+        let (again, new)  = _once(&mut queue, "second", config::DedupMode::Ignore).unwrap();
+
+        assert!(!new);
+        assert_eq!((again.id, again.data.to_string()), (original.id, String::from("first")));
+        assert_eq!(_taken(&mut queue, &consumer).as_deref(), Some("first"));
+
+        queue.expire(SystemTime::now());
+
+        let (gone, new) = _once(&mut queue, "third", config::DedupMode::Ignore).unwrap();
+
+        assert!(!new && queue.data.is_empty());
+        assert_eq!((gone.id, gone.created, gone.data.to_string()), (original.id, original.created, String::new()));
+        assert_eq!(gone.properties.dedup_id.as_deref(), Some("once"));
+    }
+
+    #[test]
+    fn rejected_duplicates_name_the_original()
+    {
+        let consumer      = testing::client(2);
+        let mut queue     = _queue(&[&consumer], &[]);
+        let (original, _) = _once(&mut queue, "first", config::DedupMode::Reject).unwrap();
+
+        assert_eq!(_once(&mut queue, "second", config::DedupMode::Reject).unwrap_err(), QueueError::Duplicate(original.id));
+
+        _taken(&mut queue, &consumer);
+        queue.expire(SystemTime::now());
+
+        assert_eq!(_once(&mut queue, "third", config::DedupMode::Reject).unwrap_err(), QueueError::Duplicate(original.id));
+        assert_eq!(queue.data.len(), 0);
+    }
+}
@@ -0,0 +1,505 @@
+//! Subscription selectors: boolean expressions over a message's `priority`, `sender` and headers,
+//! e.g. `region = 'eu' AND priority > 3`.
+//!
+//! A selector is parsed once when the subscription is made and evaluated at push time against
+//! each subscriber, so a message is only addressed to the subscribers it matches.
+//!
+//! ```text
+//! expr       := and ("OR" and)*
+//! and        := unary ("AND" unary)*
+//! unary      := "NOT" unary | "(" expr ")" | field comparison
+//! comparison := ("=" | "!=" | "<>" | "<" | "<=" | ">" | ">=") literal
+//!             | ["NOT"] "IN" "(" literal ("," literal)* ")"
+//! literal    := 'text' | number
+//! ```
+//!
+//! Keywords are case-insensitive; a `'` inside text is written `''`. A number literal compares
+//! numerically and fails against a value that is not a number; text compares as text. A field the
+//! message does not have fails every comparison. Parentheses and `NOT` nest at most `MAX_DEPTH`
+//! deep, so parsing and evaluating a selector cannot run out of stack.
+
+use super::*;
+
+/// Fields every message has; any other field names a header.
+pub const FIELDS: [&str; 2] = ["priority", "sender"];
+/// Deepest nesting of parentheses and `NOT` a selector may have.
+pub const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal
+{
+    Text(String),
+    Number(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op
+{
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr
+{
+    /// Every term holds; a chain of `AND`s is one node, not a nest.
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Compare(String, Op, Literal),
+    /// The field equals one of the values, or none of them when negated; a missing field fails either way.
+    In(String, Vec<Literal>, bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token
+{
+    Word(String),
+    Text(String),
+    Number(f64),
+    Op(Op),
+    Open,
+    Close,
+    Comma,
+}
+
+/// A parsed selector; written and displayed as its source text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector
+{
+    source: String,
+    expr:   Expr,
+}
+
+/// Whether `name` can be written as a field of a selector.
+pub fn is_field(name: &str) -> bool
+{
+    let mut chars = name.chars();
+
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+        && !["and", "or", "not", "in"].contains(&&*name.to_ascii_lowercase())
+}
+
+fn _tokens(source: &str) -> Result<Vec<Token>, String>
+{
+    let chars      = source.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i      = 0;
+
+    while i < chars.len()
+    {
+        let c = chars[i];
+
+        if c.is_whitespace() { i += 1; continue; }
+
+        let (token, next) = match c
+        {
+            '(' => (Token::Open, i + 1),
+            ')' => (Token::Close, i + 1),
+            ',' => (Token::Comma, i + 1),
+            '=' => (Token::Op(Op::Eq), i + 1),
+            '!' if chars.get(i + 1) == Some(&'=') => (Token::Op(Op::Ne), i + 2),
+            '<' if chars.get(i + 1) == Some(&'>') => (Token::Op(Op::Ne), i + 2),
+            '<' if chars.get(i + 1) == Some(&'=') => (Token::Op(Op::Le), i + 2),
+            '>' if chars.get(i + 1) == Some(&'=') => (Token::Op(Op::Ge), i + 2),
+            '<' => (Token::Op(Op::Lt), i + 1),
+            '>' => (Token::Op(Op::Gt), i + 1),
+            '\'' =>
+            {
+                let mut text = String::new();
+                let mut j    = i + 1;
+
+                loop
+                {
+                    match (chars.get(j), chars.get(j + 1))
+                    {
+                        (Some('\''), Some('\'')) => { text.push('\''); j += 2; },
+                        (Some('\''), _)          => break,
+                        (Some(c), _)             => { text.push(*c); j += 1; },
+                        (None, _)                => return Err(format!("unterminated text starting at {}", i + 1)),
+                    }
+                }
+
+                (Token::Text(text), j + 1)
+            },
+            c if c.is_ascii_digit() || c == '-' || c == '.' =>
+            {
+                let end  = (i + 1..chars.len()).find(|j| !(chars[*j].is_ascii_digit() || chars[*j] == '.')).unwrap_or(chars.len());
+                let text = chars[i..end].iter().collect::<String>();
+
+                (Token::Number(text.parse::<f64>().map_err(|_| format!("\"{}\" is not a number", text))?), end)
+            },
+            c if c.is_ascii_alphabetic() || c == '_' =>
+            {
+                let end = (i + 1..chars.len()).find(|j| !(chars[*j].is_ascii_alphanumeric() || "_.-".contains(chars[*j]))).unwrap_or(chars.len());
+
+                (Token::Word(chars[i..end].iter().collect()), end)
+            },
+            c => return Err(format!("unexpected \"{}\" at {}", c, i + 1)),
+        };
+
+        tokens.push(token);
+        i = next;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser
+{
+    tokens: Vec<Token>,
+    at:     usize,
+    depth:  usize,
+}
+
+impl Parser
+{
+    fn _peek(&self) -> Option<&Token>
+    {
+        self.tokens.get(self.at)
+    }
+
+    fn _next(&mut self) -> Option<Token>
+    {
+        let token = self.tokens.get(self.at).cloned();
+
+        self.at += 1;
+
+        token
+    }
+
+    fn _keyword(&mut self, keyword: &str) -> bool
+    {
+        match self._peek()
+        {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => { self.at += 1; true },
+            _                                                             => false,
+        }
+    }
+
+    fn _expect(&mut self, token: Token, what: &str) -> Result<(), String>
+    {
+        match self._next()
+        {
+            Some(found) if found == token => Ok(()),
+            found                         => Err(format!("expected {}, found {}", what, _describe(found.as_ref()))),
+        }
+    }
+
+    /// Runs `parse` one level deeper, failing past `MAX_DEPTH`.
+    fn _nested<F: FnOnce(&mut Parser) -> Result<Expr, String>>(&mut self, parse: F) -> Result<Expr, String>
+    {
+        if self.depth >= MAX_DEPTH { return Err(format!("nested deeper than {} levels", MAX_DEPTH)); }
+
+        self.depth += 1;
+
+        let expr = parse(self);
+
+        self.depth -= 1;
+
+        expr
+    }
+
+    fn _or(&mut self) -> Result<Expr, String>
+    {
+        let mut terms = vec![self._and()?];
+
+        while self._keyword("or") { terms.push(self._and()?); }
+
+        Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::Or(terms) })
+    }
+
+    fn _and(&mut self) -> Result<Expr, String>
+    {
+        let mut terms = vec![self._unary()?];
+
+        while self._keyword("and") { terms.push(self._unary()?); }
+
+        Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::And(terms) })
+    }
+
+    fn _unary(&mut self) -> Result<Expr, String>
+    {
+        if self._keyword("not") { return self._nested(|parser| parser._unary()).map(|expr| Expr::Not(Box::new(expr))); }
+
+        if self._peek() == Some(&Token::Open)
+        {
+            self.at += 1;
+
+            return self._nested(|parser|
+            {
+                let expr = parser._or()?;
+
+                parser._expect(Token::Close, "\")\"")?;
+
+                Ok(expr)
+            });
+        }
+
+        let field = match self._next()
+        {
+            Some(Token::Word(word)) if is_field(&word) => word,
+            found                                      => return Err(format!("expected a field, found {}", _describe(found.as_ref()))),
+        };
+
+        if self._keyword("not")
+        {
+            if !self._keyword("in") { return Err(format!("expected \"IN\", found {}", _describe(self._peek()))); }
+
+            return Ok(Expr::In(field, self._list()?, true));
+        }
+
+        if self._keyword("in") { return Ok(Expr::In(field, self._list()?, false)); }
+
+        match self._next()
+        {
+            Some(Token::Op(op)) => Ok(Expr::Compare(field, op, self._literal()?)),
+            found               => Err(format!("expected a comparison after \"{}\", found {}", field, _describe(found.as_ref()))),
+        }
+    }
+
+    fn _list(&mut self) -> Result<Vec<Literal>, String>
+    {
+        let mut values = Vec::new();
+
+        self._expect(Token::Open, "\"(\"")?;
+
+        loop
+        {
+            values.push(self._literal()?);
+
+            match self._next()
+            {
+                Some(Token::Comma) => continue,
+                Some(Token::Close) => return Ok(values),
+                found              => return Err(format!("expected \",\" or \")\", found {}", _describe(found.as_ref()))),
+            }
+        }
+    }
+
+    fn _literal(&mut self) -> Result<Literal, String>
+    {
+        match self._next()
+        {
+            Some(Token::Text(text))     => Ok(Literal::Text(text)),
+            Some(Token::Number(number)) => Ok(Literal::Number(number)),
+            found                       => Err(format!("expected 'text' or a number, found {}", _describe(found.as_ref()))),
+        }
+    }
+}
+
+fn _describe(token: Option<&Token>) -> String
+{
+    match token
+    {
+        None                        => String::from("the end"),
+        Some(Token::Word(word))     => format!("\"{}\"", word),
+        Some(Token::Text(text))     => format!("'{}'", text),
+        Some(Token::Number(number)) => format!("{}", number),
+        Some(Token::Op(_))          => String::from("an operator"),
+        Some(Token::Open)           => String::from("\"(\""),
+        Some(Token::Close)          => String::from("\")\""),
+        Some(Token::Comma)          => String::from("\",\""),
+    }
+}
+
+fn _compare(value: &str, op: Op, literal: &Literal) -> bool
+{
+    let ordering = match literal
+    {
+        Literal::Text(text)     => Some(value.cmp(text.as_str())),
+        Literal::Number(number) => value.trim().parse::<f64>().ok().and_then(|value| value.partial_cmp(number)),
+    };
+
+    match (ordering, op)
+    {
+        (None, _)                => false,
+        (Some(ordering), Op::Eq) => ordering.is_eq(),
+        (Some(ordering), Op::Ne) => ordering.is_ne(),
+        (Some(ordering), Op::Lt) => ordering.is_lt(),
+        (Some(ordering), Op::Le) => ordering.is_le(),
+        (Some(ordering), Op::Gt) => ordering.is_gt(),
+        (Some(ordering), Op::Ge) => ordering.is_ge(),
+    }
+}
+
+fn _field(message: &MSG, field: &str) -> Option<String>
+{
+    match field
+    {
+        "priority" => Some(message.priority.to_string()),
+        "sender"   => Some(message.sender.to_string()),
+        header     => message.properties.headers.get(header).cloned(),
+    }
+}
+
+fn _eval(expr: &Expr, message: &MSG) -> bool
+{
+    match expr
+    {
+        Expr::And(terms)                  => terms.iter().all(|term| _eval(term, message)),
+        Expr::Or(terms)                   => terms.iter().any(|term| _eval(term, message)),
+        Expr::Not(a)                      => !_eval(a, message),
+        Expr::Compare(field, op, literal) => _field(message, field).is_some_and(|value| _compare(&value, *op, literal)),
+        Expr::In(field, values, negated)  => _field(message, field).is_some_and(|value| values.iter().any(|literal| _compare(&value, Op::Eq, literal)) != *negated),
+    }
+}
+
+impl Selector
+{
+    pub fn matches(&self, message: &MSG) -> bool
+    {
+        _eval(&self.expr, message)
+    }
+}
+
+impl std::str::FromStr for Selector
+{
+    type Err = QueueError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err>
+    {
+        let mut parser = Parser { tokens: _tokens(source).map_err(QueueError::Invalid)?, at: 0, depth: 0 };
+        let expr       = parser._or().map_err(QueueError::Invalid)?;
+
+        if parser.at < parser.tokens.len()
+        {
+            return Err(QueueError::Invalid(format!("expected the end, found {}", _describe(parser._peek()))));
+        }
+
+        Ok(Selector { source: String::from(source.trim()), expr })
+    }
+}
+
+impl fmt::Display for Selector
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl serde::Serialize for Selector
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Selector
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        String::deserialize(deserializer)?.parse::<Selector>().map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for Selector
+{
+    fn schema_name() -> String
+    {
+        String::from("Selector")
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema
+    {
+        String::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// A message of priority 5 from `127.0.0.1:1` with `headers`.
+    fn _message(headers: &[(&str, &str)]) -> MSG
+    {
+        let sender      = Client::new(String::from("127.0.0.1"), 1);
+        let mut message = MSG::new(1, Payload::Plain(String::from("data")), sender, Vec::new(), SystemTime::now(), Some(5));
+
+        message.properties.headers = headers.iter().map(|(name, value)| (String::from(*name), String::from(*value))).collect();
+
+        message
+    }
+
+    fn _matches(source: &str, headers: &[(&str, &str)]) -> bool
+    {
+        source.parse::<Selector>().unwrap().matches(&_message(headers))
+    }
+
+    fn _error(source: &str) -> String
+    {
+        match source.parse::<Selector>()
+        {
+            Err(QueueError::Invalid(message)) => message,
+            other                             => panic!("{:?} parsed as {:?}", source, other.map(|selector| selector.to_string())),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or()
+    {
+        assert!( _matches("region = 'eu' OR region = 'us' AND priority > 9", &[("region", "eu")]));
+        assert!(!_matches("(region = 'eu' OR region = 'us') AND priority > 9", &[("region", "eu")]));
+        assert!( _matches("NOT region = 'us' AND priority = 5", &[("region", "eu")]));
+        assert!(!_matches("NOT (region = 'eu' AND priority = 5)", &[("region", "eu")]));
+    }
+
+    #[test]
+    fn quotes_and_keywords()
+    {
+        assert!( _matches("name = 'o''brien'", &[("name", "o'brien")]));
+        assert!( _matches("region in ('eu', 'us') and not priority < 5", &[("region", "us")]));
+        assert!( _matches("region NOT IN ('eu', 'us')", &[("region", "asia")]));
+        assert!( _matches("sender = '127.0.0.1:1'", &[]));
+        assert!(_error("name = 'open").contains("unterminated"));
+    }
+
+    #[test]
+    fn numbers_compare_as_numbers()
+    {
+        assert!( _matches("size >= 10", &[("size", "10.0")]));
+        assert!( _matches("size > 9", &[("size", "10")]));
+        assert!(!_matches("size > 9", &[("size", "ten")]));
+        assert!(!_matches("size > '9'", &[("size", "10")]));
+    }
+
+    #[test]
+    fn missing_headers_fail_every_comparison()
+    {
+        assert!(!_matches("region = 'eu'", &[]));
+        assert!(!_matches("region <> 'eu'", &[]));
+        assert!(!_matches("region IN ('eu')", &[]));
+        assert!(!_matches("region NOT IN ('eu')", &[]));
+        assert!( _matches("NOT region = 'eu'", &[]));
+    }
+
+    #[test]
+    fn nesting_is_capped()
+    {
+        let nested = |depth: usize| format!("{}priority = 5{}", "(".repeat(depth), ")".repeat(depth));
+        let negated = |depth: usize| format!("{}priority = 5", "NOT ".repeat(depth));
+
+        assert!(_matches(&nested(MAX_DEPTH), &[]));
+        assert!(_matches(&negated(MAX_DEPTH), &[]));
+        assert!(_error(&nested(MAX_DEPTH + 1)).contains("nested deeper"));
+        assert!(_error(&negated(MAX_DEPTH + 1)).contains("nested deeper"));
+        assert!(_error(&nested(100_000)).contains("nested deeper"));
+    }
+
+    #[test]
+    fn long_chains_stay_flat()
+    {
+        let chain = vec!["priority = 5"; 100_000].join(" AND ");
+
+        assert!(_matches(&chain, &[]));
+        assert!(_matches(&format!("{} OR priority = 1", chain), &[]));
+    }
+}
@@ -0,0 +1,257 @@
+use super::*;
+
+use iron::url::percent_encoding::percent_decode;
+
+
+pub trait ApplicationResponse
+{
+    fn json(data:    json::JsonValue, code: status::Status) -> Response;
+    fn html(content: String,          code: status::Status) -> Response;
+}
+
+
+impl ApplicationResponse for Response 
+{
+    fn json(data: json::JsonValue, code: status::Status) -> Response
+    {
+        let mut response = Response::new();
+        
+        response.set_mut(code);
+        response.set_mut(mime!(Application/Json; Charset=Utf8));
+        response.set_mut(data.dump());
+
+        response
+    }
+
+    fn html(content: String, status_code: status::Status) -> Response 
+    {
+        let mut response = Response::new();
+
+        response.set_mut(status_code);
+        response.set_mut(mime!(Text/Html; Charset=Utf8));
+        response.set_mut(content);
+
+        response
+    }
+}
+
+
+#[derive(Clone)]
+pub enum Handler<'a>
+{
+    Empty(            &'a dyn Fn()                             -> IronResult<Response>),
+    OnlyClient(       &'a dyn Fn(Client)                       -> IronResult<Response>),
+    /// The body; the handler reads its typed fields with `model::parse`.
+    ClientAndFormdata(&'a dyn Fn(Client, model::Formdata)      -> IronResult<Response>),
+    ClientAndQuery(   &'a dyn Fn(Client, urlencoded::QueryMap) -> IronResult<Response>),
+    ClientAndRequest( &'a dyn Fn(Client, &mut Request)         -> IronResult<Response>),
+    /// Path parameters and query string; the body when the route documents one, `null` otherwise.
+    ClientAndPath(    &'a dyn Fn(Client, Path, model::Formdata) -> IronResult<Response>),
+}
+
+
+/// Percent-decoded `:name` segments of the matched route, and the query string.
+#[derive(Debug, Clone, Default)]
+pub struct Path
+{
+    params:    HashMap<String, String>,
+    pub query: urlencoded::QueryMap,
+}
+
+
+impl Path
+{
+    fn from_request(request: &mut Request) -> Path
+    {
+        let params = match request.extensions.get::<Router>()
+        {
+            Some(params) => params.iter().map(|(k, v)| (String::from(k), percent_decode(v.as_bytes()).decode_utf8_lossy().into_owned())).collect(),
+            None         => HashMap::new(),
+        };
+
+        Path { params, query: request.get::<urlencoded::UrlEncodedQuery>().unwrap_or_default() }
+    }
+
+    /// The named path segment; empty if the route has none of that name.
+    pub fn get(&self, name: &str) -> String
+    {
+        self.params.get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn param(&self, name: &str) -> Option<String>
+    {
+        self.query.get(name).and_then(|values| values.first()).cloned()
+    }
+}
+
+
+unsafe impl<'a> std::marker::Sync for Handler<'a> {}
+
+
+pub struct Server
+{
+    host:       String, 
+    port:       u64,
+    threads:    usize,
+    tls:        Option<config::TlsSettings>,
+    _listening: Option<hyper::server::Listening>,
+}
+
+
+impl Server
+{
+    pub fn new(settings: &config::Settings, _router: router::Router) -> Result<Server, String>
+    {
+        let mut app = Server
+        {
+            host:    settings.host.clone(),
+            port:    settings.port,
+            threads:    settings.threads,
+            tls:        settings.tls.clone(),
+            _listening: None,
+        };
+
+        app.run(_router)?;
+
+        Ok(app)
+    }
+
+    fn get_json_data(_request: &mut Request) -> Result<serde_json::Value, QueueError>
+    {
+        let limit = config::settings().messages.max_bytes;
+
+        if _request.headers.get::<iron::headers::ContentLength>().is_some_and(|length| length.0 > limit as u64)
+        {
+            return Err(QueueError::TooLarge(format!("body is over {} bytes", limit)));
+        }
+
+        let buffer = compression::read_body(_request, limit)?;
+
+        serde_json::from_slice(&buffer).map_err(|e| QueueError::BadRequest(format!("{}", e)))
+    }
+
+    /// Reads the body of `ClientAndFormdata` routes, and of `ClientAndPath` routes that document one,
+    /// and parses it as the route's documented type; a `200` answer gets the route's documented status.
+    fn _handler(request: &mut Request, route: &str, method: String, handler: Handler, doc: &openapi::Doc) -> IronResult<Response>
+    {
+        let started = Instant::now();
+        let valide  = match (&handler, doc.body)
+        {
+            (Handler::ClientAndFormdata(_), None)             => Server::get_json_data(request).map(model::Formdata::new),
+            (Handler::ClientAndFormdata(_), Some((_, parse))) |
+            (Handler::ClientAndPath(_),     Some((_, parse))) => Server::get_json_data(request).and_then(|body| parse(&body).map(|parsed| model::Formdata::parsed(body, parsed))),
+            _                                                 => Ok(model::Formdata::new(serde_json::Value::Null)),
+        };
+        let client  =  Client::from_request(request);
+
+        let response = match valide
+        {
+            Ok(formdata) =>
+            {
+                match handler
+                {
+                    Handler::Empty(func)             => func(),
+                    Handler::OnlyClient(func)        => func(client.clone()),
+                    Handler::ClientAndFormdata(func) => func(client.clone(), formdata),
+                    Handler::ClientAndQuery(func)    => func(client.clone(), request.get::<urlencoded::UrlEncodedQuery>().unwrap_or_default()),
+                    Handler::ClientAndRequest(func)  => func(client.clone(), request),
+                    Handler::ClientAndPath(func)     => func(client.clone(), Path::from_request(request), formdata),
+                }
+            },
+            Err(err) => Ok(err.response()),
+        }.map(|mut response|
+        {
+            if response.status == Some(status::Ok) { response.status = Some(doc.status); }
+
+            compression::encode_response(request, response)
+        });
+
+        let elapsed = started.elapsed();
+        let code    = match &response
+        {
+            Ok(r)  => r.status,
+            Err(e) => e.response.status,
+        }.map(|s| s.to_u16()).unwrap_or(0);
+
+        metrics::observe_request(route, elapsed);
+        logging::event(if code >= 500 { logging::Level::Warn } else { logging::Level::Info }, "request", json::object!
+        {
+            "client"     => client.to_string(),
+            "method"     => method,
+            "route"      => route,
+            "status"     => code,
+            "latency_ms" => elapsed.as_secs_f64() * 1000.0,
+        });
+
+        response
+    }
+
+    /// Starts listening and returns; requests are served on Iron's worker threads behind a `shutdown::Gate`.
+    pub fn run(&mut self, _router: Router) -> Result<(), String>
+    {
+        let address = format!("{}:{}", self.host, self.port);
+        let mut app = Iron::new(shutdown::Gate { inner: _router });
+
+        app.threads = self.threads;
+
+        let listening = match &self.tls
+        {
+            None           => app.http(&*address).map_err(|e| format!("cannot listen on {}: {}", address, e))?,
+            Some(settings) => Self::run_https(app, address, settings)?,
+        };
+
+        self._listening = Some(listening);
+
+        Ok(())
+    }
+
+    /// Detaches the accept thread so that exiting does not join it. The socket stays open, since
+    /// hyper 0.10 cannot stop accepting (`Listening::close` only drops the join guard), so requests
+    /// that still arrive are answered `503` by the `shutdown::Gate` until the process exits.
+    pub fn detach(&mut self)
+    {
+        if let Some(mut listening) = self._listening.take()
+        {
+            if let Err(err) = listening.close() { logging::event(logging::Level::Warn, "listener not detached", json::object!{"error" => err.to_string()}); }
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn run_https(app: Iron<shutdown::Gate<Router>>, address: String, settings: &config::TlsSettings) -> Result<hyper::server::Listening, String>
+    {
+        let acceptor = tls::Acceptor::new(settings)?;
+
+        app.https(&*address, acceptor).map_err(|e| format!("cannot listen on {}: {}", address, e))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn run_https(_app: Iron<shutdown::Gate<Router>>, _address: String, _settings: &config::TlsSettings) -> Result<hyper::server::Listening, String>
+    {
+        Err(String::from("TLS is configured but miniq was built without the \"tls\" feature"))
+    }
+}
+
+
+/// Adds a route and records its `doc` for `/openapi.json`.
+pub fn router_add_path(_router: &mut router::Router, path: &str, method: &str, handler: &'static Handler, doc: openapi::Doc)
+{
+    let m     = method.to_string();
+    let route = String::from(path);
+    openapi::register(path, method, &doc);
+
+    let func  = move |r: &mut Request| Server::_handler(r, &route, m.clone(), handler.clone(), &doc);
+
+
+    if method == "post"
+    {
+        _router.post(path,   func, path);
+    } else if method == "put" {
+        _router.put(path,    func, path);
+    } else if method == "get" {
+        _router.get(path,    func, path);
+    } else if method == "delete" {
+        _router.delete(path, func, path);
+    } else {
+        _router.patch(path,  func, path);
+    }
+}
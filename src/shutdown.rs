@@ -0,0 +1,80 @@
+use super::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+static DRAINING:  AtomicBool  = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+struct InFlight;
+
+impl Drop for InFlight
+{
+    fn drop(&mut self)
+    {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Counts requests in flight and answers `503` to new ones once draining has started.
+pub struct Gate<H>
+{
+    pub inner: H,
+}
+
+impl<H: iron::Handler> iron::Handler for Gate<H>
+{
+    fn handle(&self, request: &mut Request) -> IronResult<Response>
+    {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+
+        let _guard = InFlight;
+
+        if DRAINING.load(Ordering::SeqCst)
+        {
+            let mut response = QueueError::Unavailable(String::from("server is shutting down")).response();
+
+            response.headers.set(iron::headers::Connection::close());
+
+            return Ok(response);
+        }
+
+        self.inner.handle(request)
+    }
+}
+
+/// Blocks until SIGTERM or SIGINT arrives and returns its number.
+pub fn wait_for_signal() -> Result<i32, String>
+{
+    let mut signals = Signals::new([SIGTERM, SIGINT]).map_err(|e| format!("cannot install signal handler: {}", e))?;
+
+    Ok(signals.forever().next().unwrap_or(SIGTERM))
+}
+
+/// Refuses new requests and waits up to `grace` for running ones; returns how many are still running.
+pub fn drain(grace: Duration) -> usize
+{
+    let started = SystemTime::now();
+
+    DRAINING.store(true, Ordering::SeqCst);
+
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 && started.elapsed().unwrap_or(grace) < grace
+    {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+/// Messages left in every queue that are still deliverable, by queue name.
+pub fn undelivered() -> Vec<(String, usize)>
+{
+    let now        = SystemTime::now();
+    let mut report = qgatawey::queues().iter()
+        .map(|(name, q)| (name.clone(), q.lock().unwrap().depth(now)))
+        .collect::<Vec<(String, usize)>>();
+
+    report.sort();
+
+    report
+}
@@ -0,0 +1,131 @@
+use super::*;
+use std::io;
+use std::net::{SocketAddr, Shutdown};
+use hyper::net::{HttpStream, NetworkStream, SslServer};
+use openssl::nid::Nid;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+
+lazy_static!
+{
+    /// Certificate subject of every open mutual-TLS connection, by peer address.
+    static ref PEERS: Mutex<HashMap<SocketAddr, String>> = Mutex::new(HashMap::new());
+}
+
+pub fn peer_identity(address: &SocketAddr) -> Option<String>
+{
+    PEERS.lock().unwrap().get(address).cloned()
+}
+
+#[derive(Clone)]
+pub struct Acceptor
+{
+    inner: Arc<SslAcceptor>,
+}
+
+impl Acceptor
+{
+    pub fn new(settings: &config::TlsSettings) -> Result<Acceptor, String>
+    {
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(|e| format!("{}", e))?;
+
+        builder.set_certificate_chain_file(&settings.cert).map_err(|e| format!("\"{}\": {}", settings.cert, e))?;
+        builder.set_private_key_file(&settings.key, SslFiletype::PEM).map_err(|e| format!("\"{}\": {}", settings.key, e))?;
+        builder.check_private_key().map_err(|e| format!("{}", e))?;
+
+        if let Some(ca) = &settings.client_ca
+        {
+            builder.set_ca_file(ca).map_err(|e| format!("\"{}\": {}", ca, e))?;
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        Ok(Acceptor { inner: Arc::new(builder.build()) })
+    }
+}
+
+/// Open TLS session; forgets the peer identity once the last clone is dropped.
+struct Session
+{
+    stream: SslStream<HttpStream>,
+    peer:   SocketAddr,
+}
+
+impl Drop for Session
+{
+    fn drop(&mut self)
+    {
+        PEERS.lock().unwrap().remove(&self.peer);
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsStream
+{
+    session: Arc<Mutex<Session>>,
+}
+
+impl Read for TlsStream
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.session.lock().unwrap().stream.read(buf)
+    }
+}
+
+impl Write for TlsStream
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.session.lock().unwrap().stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.session.lock().unwrap().stream.flush()
+    }
+}
+
+impl NetworkStream for TlsStream
+{
+    fn peer_addr(&mut self) -> io::Result<SocketAddr>
+    {
+        Ok(self.session.lock().unwrap().peer)
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()>
+    {
+        self.session.lock().unwrap().stream.get_ref().set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()>
+    {
+        self.session.lock().unwrap().stream.get_ref().set_write_timeout(dur)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()>
+    {
+        self.session.lock().unwrap().stream.get_mut().close(how)
+    }
+}
+
+impl SslServer<HttpStream> for Acceptor
+{
+    type Stream = TlsStream;
+
+    fn wrap_server(&self, mut stream: HttpStream) -> hyper::Result<TlsStream>
+    {
+        let peer   = stream.peer_addr()?;
+        let stream = self.inner.accept(stream).map_err(|e| hyper::Error::Ssl(Box::new(io::Error::other(format!("{}", e)))))?;
+
+        let subject = stream.ssl().peer_certificate().and_then(|cert|
+        {
+            cert.subject_name().entries_by_nid(Nid::COMMONNAME).next().and_then(|entry| entry.data().to_string().ok())
+        });
+
+        if let Some(name) = subject
+        {
+            PEERS.lock().unwrap().insert(peer, name);
+        }
+
+        Ok(TlsStream { session: Arc::new(Mutex::new(Session { stream, peer })) })
+    }
+}
@@ -0,0 +1,353 @@
+//! Transactions: pushes and acks staged against several queues, applied together or not at all.
+//!
+//! Staging only records an operation, up to `queue.max_staged` of them and `messages.max_bytes`
+//! of pushed payload. `apply` holds the queue table and every queue involved, locked in name order
+//! so two commits cannot deadlock, and runs the operations in staging order on the queues
+//! themselves, noting how to undo each; on the first failure it undoes what ran, in reverse, so
+//! subscribers never see part of a transaction. A transaction belongs to the principal that began
+//! it and is dropped after `queue.transaction_timeout` seconds without use.
+
+use super::*;
+
+/// Names of generated transactions start with this.
+pub const PREFIX: &str = "tx.";
+
+/// One staged change; `client` is who staged it, and the publisher or subscriber it acts as.
+#[derive(Debug, Clone)]
+pub enum Operation
+{
+    Push { client: Client, queue: String, message: model::Message },
+    Ack  { client: Client, queue: String, id: u64 },
+}
+
+impl Operation
+{
+    pub fn client(&self) -> &Client
+    {
+        match self
+        {
+            Operation::Push { client, .. } => client,
+            Operation::Ack { client, .. }  => client,
+        }
+    }
+
+    pub fn queue(&self) -> &str
+    {
+        match self
+        {
+            Operation::Push { queue, .. } => queue,
+            Operation::Ack { queue, .. }  => queue,
+        }
+    }
+
+    /// Payload bytes it would publish.
+    pub fn bytes(&self) -> usize
+    {
+        match self
+        {
+            Operation::Push { message, .. } => message.data.len(),
+            Operation::Ack { .. }           => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Transaction
+{
+    /// Principal of the client that began it.
+    pub owner:      String,
+    pub operations: Vec<Operation>,
+    /// Payload bytes of the staged pushes.
+    pub bytes:      usize,
+    pub last_seen:  Instant,
+}
+
+impl Transaction
+{
+    pub fn new(owner: &Client) -> Transaction
+    {
+        Transaction { owner: owner.principal(), operations: Vec::new(), bytes: 0, last_seen: Instant::now() }
+    }
+
+    /// Adds `operation` unless that takes it past `queue.max_staged` operations or
+    /// `messages.max_bytes` of payload; returns how many operations it holds.
+    pub fn stage(&mut self, operation: Operation) -> Result<usize, QueueError>
+    {
+        let settings = config::settings();
+        let bytes    = self.bytes + operation.bytes();
+
+        if self.operations.len() >= settings.queue.max_staged
+        {
+            return Err(QueueError::TooLarge(format!("a transaction stages at most {} operations", settings.queue.max_staged)));
+        }
+
+        if bytes > settings.messages.max_bytes
+        {
+            return Err(QueueError::TooLarge(format!("a transaction stages at most {} bytes of payload", settings.messages.max_bytes)));
+        }
+
+        self.bytes = bytes;
+        self.operations.push(operation);
+
+        Ok(self.operations.len())
+    }
+
+    /// Whether it went unused for `queue.transaction_timeout`.
+    pub fn is_idle(&self, now: Instant) -> bool
+    {
+        now.duration_since(self.last_seen) >= Duration::from_secs_f64(config::settings().queue.transaction_timeout)
+    }
+}
+
+/// What a commit did: the messages published, then the messages acked, each in staging order.
+/// A deduplicated push answers the original message.
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct Outcome
+{
+    pub published: Vec<MSG>,
+    pub acked:     Vec<MSG>,
+}
+
+impl std::convert::From<Outcome> for json::JsonValue
+{
+    fn from(outcome: Outcome) -> Self
+    {
+        model::to_json(&outcome)
+    }
+}
+
+/// How to take back one applied operation of queue `index`.
+enum Undo
+{
+    /// Drops the message a push appended, and its `dedup_id`.
+    Push { index: usize, id: u64, key: Option<String> },
+    /// Puts back message `before` at `position`, and the groups as they were before the ack.
+    Ack  { index: usize, position: usize, before: Box<MSG>, groups: HashMap<String, Client> },
+}
+
+fn _undo(queues: &mut [std::sync::MutexGuard<'_, Queue>], undo: Undo)
+{
+    match undo
+    {
+        Undo::Push { index, id, key } =>
+        {
+            let queue = &mut queues[index];
+
+            queue.data.retain(|message| message.id != id);
+            queue.next_id = id;
+
+            if let Some(key) = key { queue.dedup.remove(&key); }
+        },
+        Undo::Ack { index, position, before, groups } =>
+        {
+            let queue = &mut queues[index];
+
+            match queue.data.get_mut(position).filter(|message| message.id == before.id)
+            {
+                Some(message) => *message = *before,
+                None          => queue.data.insert(position, *before),
+            }
+
+            queue.groups = groups;
+        },
+    }
+}
+
+/// Runs `operations` against `broker`'s queues all at once; on the first failure nothing changes
+/// and its error is returned.
+pub fn apply(broker: &Broker, operations: &[Operation]) -> Result<Outcome, QueueError>
+{
+    let mut names = operations.iter().map(|operation| String::from(operation.queue())).collect::<Vec<String>>();
+
+    names.sort();
+    names.dedup();
+
+    // Packing may compress or write a file, so it is done before any queue is locked.
+    let mut payloads = operations.iter()
+        .filter_map(|operation| match operation
+        {
+            Operation::Push { message, .. } => Some(broker.pack(message.data.clone())),
+            _                               => None,
+        })
+        .collect::<Vec<Payload>>()
+        .into_iter();
+
+    let qs          = broker.queues();
+    let handles     = names.iter()
+        .map(|name| qs.get(name).cloned().ok_or_else(|| QueueError::NotFound(format!("\"{}\" not exists", name))))
+        .collect::<Result<Vec<Arc<Mutex<Queue>>>, QueueError>>()?;
+    let mut guards  = handles.iter().map(|q| q.lock().unwrap()).collect::<Vec<_>>();
+    let mut undo    = Vec::new();
+    let mut fresh   = Vec::new();
+    let mut acks    = Vec::new();
+    let mut outcome = Outcome::default();
+    let now         = SystemTime::now();
+
+    for operation in operations
+    {
+        let index  = names.binary_search_by(|name| name.as_str().cmp(operation.queue())).unwrap();
+        let queue  = &mut guards[index];
+        let result = match operation
+        {
+            Operation::Push { client, message, .. } => queue.append(payloads.next().expect("a payload per push"), client.clone(), message.lifetime, message.priority, message.properties.clone()).and_then(|(msg, new)|
+            {
+                if new
+                {
+                    undo.push(Undo::Push { index, id: msg.id, key: msg.properties.dedup_id.clone() });
+                    fresh.push((index, msg.clone()));
+                }
+
+                outcome.published.push(msg.loaded()?);
+
+                Ok(())
+            }),
+            Operation::Ack { client, id, .. } =>
+            {
+                let position = queue.data.iter().position(|message| message.id == *id);
+                let before   = position.map(|position| (position, Box::new(queue.data[position].clone()), queue.groups.clone()));
+
+                queue.take(client, *id, now).map(|msg|
+                {
+                    if let Some((position, before, groups)) = before { undo.push(Undo::Ack { index, position, before, groups }); }
+
+                    outcome.acked.push(msg);
+                    acks.push((index, client.clone()));
+                })
+            },
+        };
+
+        if let Err(err) = result
+        {
+            for step in undo.into_iter().rev() { _undo(&mut guards, step); }
+
+            return Err(err);
+        }
+    }
+
+    drop(qs);
+
+    for (index, msg) in &fresh { guards[*index].published(msg); }
+
+    broker.memory.charge(fresh.iter().map(|(_, msg)| msg.footprint()).sum());
+    for (index, client) in &acks { guards[*index].acked(client); }
+
+    Ok(outcome)
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::testing;
+
+    fn _push(client: &Client, queue: &str, data: &str, properties: Properties) -> Operation
+    {
+        Operation::Push { client: client.clone(), queue: String::from(queue), message: model::Message { data: String::from(data), properties, ..model::Message::default() } }
+    }
+
+    fn _ack(client: &Client, queue: &str, id: u64) -> Operation
+    {
+        Operation::Ack { client: client.clone(), queue: String::from(queue), id }
+    }
+
+    /// Ids, activity and recipients of every message of `name`, with its next id and dedup keys.
+    fn _state(broker: &Broker, name: &str) -> (Vec<(u64, bool, usize)>, u64, usize, usize)
+    {
+        broker.update(name, |queue| Ok((queue.data.iter().map(|message| (message.id, message.active, message.recipients.len())).collect(), queue.next_id, queue.dedup.len(), queue.groups.len()))).unwrap()
+    }
+
+    #[test]
+    fn commit_applies_every_operation()
+    {
+        let (broker, client) = testing::broker(&["a", "b"]);
+        let waiting          = broker.publish(&client, "b", String::from("waiting"), None, None).unwrap();
+        let id               = broker.begin(&client);
+
+        broker.stage(&id, _push(&client, "a", "staged", Properties::default())).unwrap();
+        broker.stage(&id, _ack(&client, "b", waiting.id)).unwrap();
+
+        assert!(broker.consume(&client, "a").unwrap().is_none());
+
+        let outcome = broker.commit(&client, &id).unwrap();
+
+        assert_eq!(outcome.published.len(), 1);
+        assert_eq!(outcome.acked[0].id, waiting.id);
+        assert_eq!(broker.consume(&client, "a").unwrap().map(|message| message.data.to_string()).as_deref(), Some("staged"));
+        assert!(broker.consume(&client, "b").unwrap().is_none());
+        assert!(matches!(broker.commit(&client, &id), Err(QueueError::NotFound(_))));
+    }
+
+    #[test]
+    fn failed_commit_changes_nothing()
+    {
+        let (broker, client) = testing::broker(&["a", "b"]);
+        let grouped          = Properties { group_id: Some(String::from("g")), ..Properties::default() };
+        let waiting          = broker.publish_with(&client, "b", String::from("waiting"), None, None, grouped.clone()).unwrap();
+        let before           = (_state(&broker, "a"), _state(&broker, "b"));
+        let id               = broker.begin(&client);
+        let keyed            = Properties { dedup_id: Some(String::from("k")), ..Properties::default() };
+
+        broker.stage(&id, _push(&client, "a", "first", keyed)).unwrap();
+        broker.stage(&id, _push(&client, "b", "second", grouped)).unwrap();
+        broker.stage(&id, _ack(&client, "b", waiting.id)).unwrap();
+        broker.stage(&id, _ack(&client, "a", 99)).unwrap();
+
+        assert!(matches!(broker.commit(&client, &id), Err(QueueError::NotFound(_))));
+        assert_eq!((_state(&broker, "a"), _state(&broker, "b")), before);
+        assert_eq!(broker.consume(&client, "b").unwrap().map(|message| message.id), Some(waiting.id));
+    }
+
+    #[test]
+    fn rollback_discards_the_staged_operations()
+    {
+        let (broker, client) = testing::broker(&["a", "b"]);
+        let id               = broker.begin(&client);
+
+        broker.stage(&id, _push(&client, "a", "staged", Properties::default())).unwrap();
+
+        assert!(matches!(broker.rollback(&Client::new(String::from("10.0.0.2"), 1), &id), Err(QueueError::Forbidden(_))));
+        assert_eq!(broker.rollback(&client, &id).unwrap(), 1);
+        assert!(matches!(broker.commit(&client, &id), Err(QueueError::NotFound(_))));
+        assert_eq!(_state(&broker, "a").0, Vec::new());
+    }
+
+    #[test]
+    fn staging_stops_at_the_transaction_limits()
+    {
+        let (broker, client) = testing::broker(&["a", "b"]);
+        let settings         = config::settings();
+        let id               = broker.begin(&client);
+
+        for _ in 0..settings.queue.max_staged { broker.stage(&id, _ack(&client, "a", 1)).unwrap(); }
+
+        assert!(matches!(broker.stage(&id, _ack(&client, "a", 1)), Err(QueueError::TooLarge(_))));
+
+        let id   = broker.begin(&client);
+        let half = "x".repeat(settings.messages.max_bytes / 2 + 1);
+
+        broker.stage(&id, _push(&client, "a", &half, Properties::default())).unwrap();
+
+        assert!(matches!(broker.stage(&id, _push(&client, "a", &half, Properties::default())), Err(QueueError::TooLarge(_))));
+        assert_eq!(broker.rollback(&client, &id).unwrap(), 1);
+    }
+
+    #[test]
+    fn idle_transaction_is_dropped()
+    {
+        let (broker, client) = testing::broker(&["a", "b"]);
+        let id               = broker.begin(&client);
+        let timeout          = Duration::from_secs_f64(config::settings().queue.transaction_timeout);
+
+        broker.stage(&id, _push(&client, "a", "staged", Properties::default())).unwrap();
+        broker.expire();
+
+        assert!(broker.transactions.lock().unwrap().contains_key(&id));
+
+        broker.transactions.lock().unwrap().get_mut(&id).unwrap().last_seen = Instant::now() - timeout;
+        broker.expire();
+
+        assert!(matches!(broker.commit(&client, &id), Err(QueueError::NotFound(_))));
+        assert_eq!(_state(&broker, "a").0, Vec::new());
+    }
+}
@@ -0,0 +1,286 @@
+//! `/v2`: the broker as resources, served next to the legacy RPC-style routes.
+//!
+//! Queues live at `/v2/queues/{name}`, with `messages`, `subscribers`, `publishers` and `acl`
+//! below them; `messages/{id}/data` is one payload on its own, for those too large to page
+//! through. A `{client}` segment is `host:port`, a certificate identity, or `me` for the caller;
+//! subscribing or unsubscribing anyone but yourself takes `manage` on the queue.
+//! `PUT` is idempotent: adding a subscriber or publisher that is already there answers the
+//! current list instead of `409`. A subscriber's `?selector=` replaces the one it had.
+
+use super::*;
+
+/// `{client}` segment standing for the caller.
+pub const ME: &str = "me";
+
+/// A success answers `200`, which `Server::_handler` turns into the route's documented status.
+fn _respond(result: Result<json::JsonValue, QueueError>) -> IronResult<Response>
+{
+    match result
+    {
+        Ok(data) => Ok(Response::json(data, status::Ok)),
+        Err(err) => Ok(err.response()),
+    }
+}
+
+fn _target(caller: &Client, segment: &str) -> Result<Client, QueueError>
+{
+    if segment == ME { return Ok(caller.clone()); }
+
+    Client::parse(segment).ok_or_else(|| QueueError::Invalid(format!("\"{}\" is not host:port", segment)))
+}
+
+/// `POST /v2/queues`: answers `201` with the new queue's metadata.
+pub fn create_queue(client: Client, _path: Path, formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = model::parse::<model::QueueName>(formdata).and_then(|body| {
+        qgatawey::broker().create(&client, &body.name)?;
+        qgatawey::broker().update(&body.name, |queue| listing::info(&client, queue))
+    });
+
+    _respond(result)
+}
+
+pub fn queue_info(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _respond(qgatawey::broker().update(&path.get("name"), |queue| listing::info(&client, queue)))
+}
+
+pub fn delete_queue(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    let name = path.get("name");
+
+    _respond(qgatawey::broker().delete(&client, &name).map(|messages| model::to_json(&model::Deleted { name: name.clone(), messages })))
+}
+
+/// `GET /v2/queues/{name}/messages`: takes the `/messages` query parameters except `name`.
+pub fn list_messages(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = listing::MessageQuery::parse(&|name: &str| path.param(name))
+        .and_then(|filter| qgatawey::broker().update(&path.get("name"), |queue| { queue.touch(&client); listing::messages(&client, queue, &filter) }));
+
+    _respond(result)
+}
+
+/// `GET /v2/queues/{name}/messages/{id}/data`: the payload alone as text; a spilled payload is
+/// streamed from its file and never compressed.
+pub fn message_data(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = path.get("id").parse::<u64>()
+        .map_err(|_| QueueError::Invalid(format!("\"{}\" is not a message id", path.get("id"))))
+        .and_then(|id| qgatawey::broker().update(&path.get("name"), |queue| {
+            queue.acl.check(&client, acl::Permission::Subscribe, &queue.name)?;
+            queue.touch(&client);
+            queue.data.iter().find(|message| message.id == id).map(|message| message.data.clone()).ok_or_else(|| QueueError::NotFound(format!("no message {} in \"{}\"", id, queue.name)))
+        }));
+
+    let mut response = Response::new();
+
+    response.set_mut(status::Ok);
+    response.set_mut(mime!(Text/Plain; Charset=Utf8));
+
+    match result
+    {
+        Ok(Payload::Spilled(blob)) => match blob.open()
+        {
+            Ok(file) =>
+            {
+                response.headers.set(iron::headers::ContentLength(blob.len() as u64));
+                response.headers.set(iron::headers::ContentEncoding(vec![iron::headers::Encoding::Identity]));
+                response.body = Some(Box::new(file));
+            },
+            Err(err) => return Ok(QueueError::NotFound(format!("payload is gone: {}", err)).response()),
+        },
+        Ok(payload) => match payload.text()
+        {
+            Ok(text) => { response.set_mut(text); },
+            Err(err) => return Ok(err.response()),
+        },
+        Err(err)    => return Ok(err.response()),
+    }
+
+    Ok(response)
+}
+
+/// `POST /v2/queues/{name}/messages/next`: takes the next message waiting for the caller, `null`
+/// when there is none yet.
+pub fn consume(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _respond(qgatawey::broker().consume(&client, &path.get("name")).map(|message| message.map_or(json::JsonValue::Null, json::JsonValue::from)))
+}
+
+/// `DELETE /v2/queues/{name}/messages/{id}`: acknowledges message `id`, taking it off the queue
+/// for the caller whatever its priority.
+pub fn ack(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = path.get("id").parse::<u64>()
+        .map_err(|_| QueueError::Invalid(format!("\"{}\" is not a message id", path.get("id"))))
+        .and_then(|id| qgatawey::broker().ack(&client, &path.get("name"), id));
+
+    _respond(result.map(json::JsonValue::from))
+}
+
+/// `POST /v2/queues/{name}/messages`: answers `201` with the stored message.
+pub fn publish(client: Client, path: Path, formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = model::parse::<model::Message>(formdata)
+        .and_then(|body| qgatawey::broker().publish_with(&client, &path.get("name"), body.data, body.lifetime, body.priority, body.properties));
+
+    _respond(result.map(json::JsonValue::from))
+}
+
+/// `POST /v2/queues/{name}/requests`: publishes the body and answers the reply, waiting at most
+/// `?timeout` seconds and no longer than `queue.reply_timeout`; `reply_to` and `correlation_id`
+/// are set by the broker.
+pub fn request(client: Client, path: Path, formdata: model::Formdata) -> IronResult<Response>
+{
+    let timeout = match path.param("timeout").map(|v| v.parse::<f64>().ok().filter(|t| *t > 0.0 && *t <= model::MAX_LIFETIME).ok_or(v))
+    {
+        Some(Err(v))      => return Ok(QueueError::Invalid(format!("\"timeout\" \"{}\" is not a positive number", v)).response()),
+        Some(Ok(timeout)) => Some(timeout),
+        None              => None,
+    };
+    let result  = model::parse::<model::Message>(formdata)
+        .and_then(|body| qgatawey::broker().request(&client, &path.get("name"), body.data, body.lifetime, body.priority, timeout));
+
+    _respond(result.map(json::JsonValue::from))
+}
+
+/// `POST /v2/reply_queues`: answers `201` with the generated name of an exclusive reply queue.
+pub fn create_reply_queue(client: Client, _path: Path, formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = model::parse::<model::ReplyQueue>(formdata)
+        .and_then(|body| qgatawey::broker().create_reply_queue(&client, body.idle_timeout))
+        .map(|name| model::to_json(&model::QueueName { name }));
+
+    _respond(result)
+}
+
+/// `POST /v2/transactions`: answers `201` with the generated name of a new transaction.
+pub fn begin_transaction(client: Client, _path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _respond(Ok(model::to_json(&model::TransactionId { transaction: qgatawey::broker().begin(&client) })))
+}
+
+fn _staged(path: &Path, result: Result<usize, QueueError>) -> IronResult<Response>
+{
+    _respond(result.map(|operations| model::to_json(&model::Staged { transaction: path.get("id"), operations })))
+}
+
+/// `POST /v2/transactions/{id}/messages`: stages a `/push` body.
+pub fn stage_push(client: Client, path: Path, formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = model::parse::<model::Push>(formdata).and_then(|body| {
+        let operation = transaction::Operation::Push { client, queue: body.name, message: body.message };
+
+        qgatawey::broker().stage(&path.get("id"), operation)
+    });
+
+    _staged(&path, result)
+}
+
+/// `POST /v2/transactions/{id}/acks`.
+pub fn stage_ack(client: Client, path: Path, formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = model::parse::<model::Ack>(formdata)
+        .and_then(|body| qgatawey::broker().stage(&path.get("id"), transaction::Operation::Ack { client, queue: body.name, id: body.id }));
+
+    _staged(&path, result)
+}
+
+/// `POST /v2/transactions/{id}/commit`.
+pub fn commit(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _respond(qgatawey::broker().commit(&client, &path.get("id")).map(json::JsonValue::from))
+}
+
+/// `DELETE /v2/transactions/{id}`: rolls back.
+pub fn rollback(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _staged(&path, qgatawey::broker().rollback(&client, &path.get("id")))
+}
+
+fn _subscriber(client: Client, path: Path, subscribe: bool) -> IronResult<Response>
+{
+    let name     = path.get("name");
+    let selector = match path.param("selector").map(|source| source.parse::<selector::Selector>()).transpose()
+    {
+        Ok(selector) => selector,
+        Err(err)     => return Ok(QueueError::Invalid(format!("\"selector\" {}", err.message())).response()),
+    };
+    let result   = _target(&client, &path.get("client")).and_then(|target| qgatawey::broker().update(&name, |queue| {
+        if target != client { queue.acl.check(&client, acl::Permission::Manage, &name)?; }
+
+        match (subscribe, queue.subscribers.contains(&target))
+        {
+            (true, true) => queue.select(&target, selector),
+            (true, _)    => queue.sub_with(target, selector),
+            (false, _)   => queue.unsub(target),
+        }
+    }));
+
+    _respond(result.map(json::JsonValue::from))
+}
+
+pub fn put_subscriber(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _subscriber(client, path, true)
+}
+
+pub fn delete_subscriber(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _subscriber(client, path, false)
+}
+
+fn _publisher(client: Client, path: Path, add: bool) -> IronResult<Response>
+{
+    let result = _target(&client, &path.get("client")).and_then(|target| qgatawey::broker().update(&path.get("name"), |queue| {
+        match (add, queue.publishers.contains(&target))
+        {
+            (true, true) => queue.acl.check(&client, acl::Permission::Manage, &queue.name).map(|_| queue.publishers.clone()),
+            (true, _)    => queue.add_publisher(&client, target),
+            (false, _)   => queue.remove_publisher(&client, target),
+        }
+    }));
+
+    _respond(result.map(json::JsonValue::from))
+}
+
+pub fn put_publisher(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _publisher(client, path, true)
+}
+
+pub fn delete_publisher(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _publisher(client, path, false)
+}
+
+fn _grant(client: Client, path: Path, grant: bool) -> IronResult<Response>
+{
+    let principal = path.get("principal");
+    let result    = path.get("permission").parse::<acl::Permission>().and_then(|permission| qgatawey::broker().update(&path.get("name"), |queue| {
+        if grant { queue.grant(&client, principal, permission) } else { queue.revoke(&client, principal, permission) }
+    }));
+
+    _respond(result)
+}
+
+/// `PUT /v2/queues/{name}/acl/{principal}/{permission}`.
+pub fn put_grant(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _grant(client, path, true)
+}
+
+pub fn delete_grant(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    _grant(client, path, false)
+}
+
+/// `PUT /v2/roles/{principal}/{role}`: broker-wide `manage` only.
+pub fn put_role(client: Client, path: Path, _formdata: model::Formdata) -> IronResult<Response>
+{
+    let result = acl::GLOBAL.lock().unwrap().check(&client, acl::Permission::Manage, "broker")
+        .map(|_| json::JsonValue::from(acl::assign_role(path.get("principal"), path.get("role"))));
+
+    _respond(result)
+}